@@ -2,18 +2,105 @@ use specs;
 
 pub const BODY_GRAVITY : u32 = 1;
 
+/// How a body is moved by physics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyType {
+  /// Never moves, and has infinite mass in collisions - e.g. floors and
+  /// walls.
+  Static,
+  /// Moves only by its velocity, which is set by gameplay code. Ignores
+  /// forces and gravity, and has infinite mass in collisions, so it pushes
+  /// dynamic bodies but is never pushed back - e.g. moving platforms.
+  Kinematic,
+  /// Moved by forces, gravity and collisions.
+  Dynamic,
+}
+
 /// A component representing a physical body in the world. Should be coupled
 /// with an AABB component.
 pub struct CompBody {
-  /// Acceleration vector
+  /// Constant acceleration vector, applied every step regardless of mass
   pub acc: [f32; 2],
   /// Velocity vector
   pub vel: [f32; 2],
-  /// Mass in KG
+  /// Mass in KG. Only used by dynamic bodies, and must be more than 0 for
+  /// them.
   pub mass: f32,
+  /// How the body is moved by physics
+  pub body_type: BodyType,
   /// Bitflags indicating properties of this body
   /// * BIT 0 - Gravity. 1 for this body to be affected by gravity, 0 for not.
   pub flags: u32,
+  /// How strongly gravity affects this body, e.g. 0.5 for half gravity, or
+  /// -1 to fall upwards. Only used if the gravity flag is set.
+  pub gravity_scale: f32,
+  /// The total force applied this physics step. Cleared after each step.
+  pub force: [f32; 2],
+  /// The total torque applied this physics step, from forces applied off
+  /// center. Cleared after each step.
+  pub torque: f32,
+  /// Rotation in radians, from +X towards +Y, about the centroid. Only
+  /// affects entities with a `CompShape`.
+  pub angle: f32,
+  /// Angular velocity in radians per second
+  pub ang_vel: f32,
+  /// Moment of inertia about the centroid, e.g. from `CompShape::inertia`.
+  /// Bodies with a moment of inertia of 0 or less never rotate by forces or
+  /// collisions.
+  pub inertia: f32,
+}
+
+impl Default for CompBody {
+  /// A dynamic body with a mass of 1 which is affected by gravity, at rest,
+  /// and which can't rotate.
+  fn default() -> CompBody {
+    CompBody {
+      acc: [0.0, 0.0], vel: [0.0, 0.0], mass: 1.0, body_type: BodyType::Dynamic, flags: BODY_GRAVITY,
+      gravity_scale: 1.0, force: [0.0, 0.0], torque: 0.0, angle: 0.0, ang_vel: 0.0, inertia: 0.0,
+    }
+  }
+}
+
+impl CompBody {
+  /// Applies a force through the body's center for the rest of this physics
+  /// step. Forces are accumulated, and cleared after the step.
+  pub fn apply_force(&mut self, force: [f32; 2]) {
+    self.force[0] += force[0];
+    self.force[1] += force[1];
+  }
+
+  /// Applies a force at a point for the rest of this physics step. Forces
+  /// applied off center also apply a torque.
+  /// # Params
+  /// * `force` - The force to apply
+  /// * `point` - Where to apply the force, in world coordinates
+  /// * `center` - The body's center of mass, in world coordinates
+  pub fn apply_force_at_point(&mut self, force: [f32; 2], point: [f32; 2], center: [f32; 2]) {
+    self.apply_force(force);
+    let r = [point[0] - center[0], point[1] - center[1]];
+    self.torque += r[0] * force[1] - r[1] * force[0];
+  }
+
+  /// Applies an impulse through the body's center, changing its velocity
+  /// immediately. Only affects dynamic bodies.
+  pub fn apply_impulse(&mut self, impulse: [f32; 2]) {
+    let inv_mass = self.inv_mass();
+    self.vel[0] += impulse[0] * inv_mass;
+    self.vel[1] += impulse[1] * inv_mass;
+  }
+
+  /// Gets the inverse of the body's mass, for integrating forces and for
+  /// collision response. This is 0 for static and kinematic bodies, as their
+  /// mass is infinite, and for bodies with no mass, which forces can't move.
+  pub fn inv_mass(&self) -> f32 {
+    if self.body_type == BodyType::Dynamic && self.mass > 0.0 { 1.0 / self.mass } else { 0.0 }
+  }
+
+  /// Gets the inverse of the body's moment of inertia. This is 0 for bodies
+  /// which can't be rotated by forces or collisions.
+  pub fn inv_inertia(&self) -> f32 {
+    if self.body_type == BodyType::Dynamic && self.inertia > 0.0 { 1.0 / self.inertia } else { 0.0 }
+  }
 }
 
 impl specs::Component for CompBody {
@@ -26,10 +113,27 @@ impl specs::Component for CompAABB {
   type Storage = specs::VecStorage<CompAABB>;
 }
 
+#[allow(dead_code)]
 pub struct CompVel(pub [f32; 2]);
 impl specs::Component for CompVel {
   type Storage = specs::VecStorage<CompVel>;
 }
 
+/// The entity's `CompAABB` as of the previous physics step - X, Y, W, H
+/// format. Entities with this are drawn interpolated between their previous
+/// and current AABBs, so they move smoothly whatever the frame rate.
+pub struct CompPrevAABB(pub [f32; 4]);
+impl specs::Component for CompPrevAABB {
+  type Storage = specs::VecStorage<CompPrevAABB>;
+}
+
+/// The `CompBody` angle as of the previous physics step. Entities with a
+/// `CompShape`, a `CompPrevAABB` and this are drawn with their shape's
+/// position and rotation interpolated between the last 2 steps.
+pub struct CompPrevAngle(pub f32);
+impl specs::Component for CompPrevAngle {
+  type Storage = specs::VecStorage<CompPrevAngle>;
+}
+
 
 
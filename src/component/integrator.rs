@@ -0,0 +1,9 @@
+use specs;
+use physics::Integrator;
+
+/// Integrator component - overrides the integrator `physics::RigidBody` uses
+/// for this entity's `CompBody`.
+pub struct CompIntegrator(pub Integrator);
+impl specs::Component for CompIntegrator {
+  type Storage = specs::HashMapStorage<CompIntegrator>;
+}
@@ -1,7 +1,18 @@
 mod color;
 mod body;
+mod polygon;
+mod region;
+mod integrator;
+mod shape;
 
 pub use self::color::CompColor;
 pub use self::body::CompBody;
 pub use self::body::CompAABB;
+pub use self::body::CompPrevAABB;
+pub use self::body::CompPrevAngle;
 pub use self::body::BODY_GRAVITY;
+pub use self::body::BodyType;
+pub use self::polygon::CompPolygon;
+pub use self::region::CompRegion;
+pub use self::integrator::CompIntegrator;
+pub use self::shape::CompShape;
@@ -0,0 +1,9 @@
+use specs;
+
+/// Polygon component - a list of points, counter-clockwise (positive signed
+/// area). Filled by the renderer with the entity's `CompColor`. The polygon
+/// may be concave, but mustn't intersect itself.
+pub struct CompPolygon(pub Vec<[f32; 2]>);
+impl specs::Component for CompPolygon {
+  type Storage = specs::VecStorage<CompPolygon>;
+}
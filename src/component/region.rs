@@ -0,0 +1,9 @@
+use specs;
+
+/// Region component - the index of the terrain region (Voronoi cell) an
+/// entity's `CompAABB` is centered in, or `None` if it's outside the map. Kept
+/// up to date by `terrain::SysLocateRegion`.
+pub struct CompRegion(pub Option<usize>);
+impl specs::Component for CompRegion {
+  type Storage = specs::VecStorage<CompRegion>;
+}
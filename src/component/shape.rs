@@ -0,0 +1,135 @@
+use specs;
+
+/// The number of sides used to approximate a circle's outline.
+const CIRCLE_SIDES: usize = 24;
+
+/// Shape component - the collision shape of an entity's `CompBody`, rotated
+/// by the body's angle about its centroid. Physics keeps the `CompAABB` fitted
+/// around the rotated shape, so the AABB and angle together give where the
+/// shape is. Entities without a shape collide as their `CompAABB`, and never
+/// rotate.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompShape {
+  /// A box, given its half width and half height
+  Box([f32; 2]),
+  /// A circle, given its radius
+  Circle(f32),
+  /// A convex polygon, given its points relative to any origin - it needn't
+  /// be the centroid - counter-clockwise (positive signed area)
+  Polygon(Vec<[f32; 2]>),
+}
+
+impl specs::Component for CompShape {
+  type Storage = specs::VecStorage<CompShape>;
+}
+
+/// Rotates a point about `about`, then moves `about` to `center`.
+fn transform(p: [f32; 2], about: [f32; 2], center: [f32; 2], angle: f32) -> [f32; 2] {
+  let (sin, cos) = angle.sin_cos();
+  let p = [p[0] - about[0], p[1] - about[1]];
+  [center[0] + p[0] * cos - p[1] * sin, center[1] + p[0] * sin + p[1] * cos]
+}
+
+impl CompShape {
+  /// Gets the centroid of the shape, relative to the origin its points are
+  /// given from. Boxes and circles are centered on their origin.
+  pub fn centroid(&self) -> [f32; 2] {
+    match *self {
+      CompShape::Polygon(ref points) => {
+        let (mut sum, mut area) = ([0.0, 0.0], 0.0);
+        for ii in 0..points.len() {
+          let (a, b) = (points[ii], points[(ii + 1) % points.len()]);
+          let cross = a[0] * b[1] - a[1] * b[0];
+          sum[0] += (a[0] + b[0]) * cross;
+          sum[1] += (a[1] + b[1]) * cross;
+          area += cross;
+        }
+        if area != 0.0 { [sum[0] / (3.0 * area), sum[1] / (3.0 * area)] } else { [0.0, 0.0] }
+      }
+      _ => [0.0, 0.0],
+    }
+  }
+
+  /// Finds where the shape's centroid is, given the AABB around it.
+  /// # Params
+  /// * `aabb` - The AABB fitted around the rotated shape, as from `bounds`
+  /// * `angle` - The rotation of the shape in radians
+  /// # Returns
+  /// The position of the centroid in world space
+  pub fn centroid_in(&self, aabb: &[f32; 4], angle: f32) -> [f32; 2] {
+    let offset = self.bounds([0.0, 0.0], angle);
+    [aabb[0] - offset[0], aabb[1] - offset[1]]
+  }
+
+  /// Gets the corners of the shape in world space. Circles are approximated
+  /// by a polygon.
+  /// # Params
+  /// * `center` - The position of the shape's centroid
+  /// * `angle` - The rotation of the shape about its centroid in radians,
+  ///   from +X towards +Y
+  /// # Returns
+  /// The corners, counter-clockwise (positive signed area)
+  pub fn outline(&self, center: [f32; 2], angle: f32) -> Vec<[f32; 2]> {
+    match *self {
+      CompShape::Box(h) => {
+        [[-h[0], -h[1]], [h[0], -h[1]], [h[0], h[1]], [-h[0], h[1]]].iter()
+          .map(|&p| transform(p, [0.0, 0.0], center, angle)).collect()
+      }
+      CompShape::Circle(r) => (0..CIRCLE_SIDES).map(|ii| {
+        let a = angle + ii as f32 * 2.0 * ::std::f32::consts::PI / CIRCLE_SIDES as f32;
+        [center[0] + r * a.cos(), center[1] + r * a.sin()]
+      }).collect(),
+      CompShape::Polygon(ref points) => {
+        let c = self.centroid();
+        points.iter().map(|&p| transform(p, c, center, angle)).collect()
+      }
+    }
+  }
+
+  /// Gets the AABB around the shape.
+  /// # Params
+  /// * `center` - The position of the shape's centroid
+  /// * `angle` - The rotation of the shape in radians, about its centroid
+  /// # Returns
+  /// The AABB - X, Y, W, H
+  pub fn bounds(&self, center: [f32; 2], angle: f32) -> [f32; 4] {
+    if let CompShape::Circle(r) = *self {
+      return [center[0] - r, center[1] - r, r * 2.0, r * 2.0];
+    }
+    let points = self.outline(center, angle);
+    let (mut min, mut max) = ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]);
+    for p in &points {
+      for axis in 0..2 {
+        min[axis] = min[axis].min(p[axis]);
+        max[axis] = max[axis].max(p[axis]);
+      }
+    }
+    [min[0], min[1], max[0] - min[0], max[1] - min[1]]
+  }
+
+  /// Gets the moment of inertia of the shape about its centroid, assuming
+  /// uniform density.
+  /// # Params
+  /// * `mass` - The mass of the shape
+  pub fn inertia(&self, mass: f32) -> f32 {
+    match *self {
+      CompShape::Box(h) => mass * (h[0] * h[0] + h[1] * h[1]) / 3.0,
+      CompShape::Circle(r) => mass * r * r / 2.0,
+      CompShape::Polygon(ref points) => {
+        // Sum the inertia of the triangles fanning out from the centroid.
+        // Their areas are signed, so triangles outside the shape cancel out
+        // wherever the fan starts.
+        let c = self.centroid();
+        let points : Vec<[f32; 2]> = points.iter().map(|p| [p[0] - c[0], p[1] - c[1]]).collect();
+        let (mut num, mut den) = (0.0, 0.0);
+        for ii in 0..points.len() {
+          let (a, b) = (points[ii], points[(ii + 1) % points.len()]);
+          let cross = a[0] * b[1] - a[1] * b[0];
+          num += cross * (a[0] * a[0] + a[1] * a[1] + a[0] * b[0] + a[1] * b[1] + b[0] * b[0] + b[1] * b[1]);
+          den += cross;
+        }
+        if den > 0.0 { mass * num / (6.0 * den) } else { 0.0 }
+      }
+    }
+  }
+}
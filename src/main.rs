@@ -1,3 +1,7 @@
+// `Option::is_none_or` needs Rust 1.82, far newer than anything else here
+// needs, so `map_or(true, ..)` is used instead
+#![allow(unknown_lints, clippy::unnecessary_map_or)]
+
 #[macro_use]
 extern crate glium;
 extern crate specs;
@@ -12,10 +16,9 @@ pub mod terrain;
 pub mod physics;
 
 use component::*;
-use state::GlobalState;
+use state::{GlobalState, Stage};
 use glium::backend::glutin_backend::GlutinFacade;
 use glium::glutin::Event;
-use rand::{Rng, StdRng};
 
 fn init_display() -> GlutinFacade {
     use glium::DisplayBuild;
@@ -29,53 +32,130 @@ fn init_display() -> GlutinFacade {
 fn main() {
   let display = init_display();
 
-  let mut global_state = GlobalState { delta: 0, prev_time: time::precise_time_ns() };
+  // Take the world seed from the command line if given, so worlds from bug
+  // reports can be reproduced
+  let seed = std::env::args().nth(1)
+    .map(|s| s.parse().expect("Seed must be an unsigned integer"))
+    .unwrap_or_else(time::precise_time_ns);
+  println!("World seed: {}", seed);
+
+  let mut global_state = GlobalState {
+    delta: 0, prev_time: time::precise_time_ns(), seed, stage: Stage::Frame, alpha: 0.0,
+  };
+  let mut timestep = physics::FixedTimestep::default();
+
+  // Generate the world map, covering the whole window
+  let (win_w, win_h) = display.get_window().unwrap().get_inner_size().unwrap();
+  let map_config = terrain::map::MapConfig {
+    bounds: [0.0, 0.0, win_w as f32, win_h as f32],
+    .. Default::default()
+  };
+  let map = terrain::map::TerrainMap::generate(&map_config, global_state.seed);
+
+  let settlements = terrain::road::pick_settlements(&map, 8, global_state.seed);
+  let roads = terrain::road::RoadNetwork::generate(&map, &settlements, &Default::default());
+
+  // Export the map if a path is given after the seed, e.g. `specs_test 42 map`
+  // writes map.svg and map.json
+  if let Some(path) = std::env::args().nth(2) {
+    use terrain::export;
+    std::fs::write(format!("{}.svg", path), export::map_to_svg(&map, export::ColorBy::Biome))
+      .expect("Failed to write SVG");
+    std::fs::write(format!("{}.json", path), export::map_to_json(&map, &roads))
+      .expect("Failed to write JSON");
+  }
 
   // Create ECS
   let mut planner : specs::Planner<GlobalState> = {
     let mut w = specs::World::new();
     w.register::<CompAABB>();
+    w.register::<CompPrevAABB>();
+    w.register::<CompPrevAngle>();
     w.register::<CompBody>();
     w.register::<CompColor>();
-    w.create_now().with(CompAABB([0.0, 0.0, 32.0, 32.0]))
+    w.register::<CompPolygon>();
+    w.register::<CompRegion>();
+    w.register::<CompIntegrator>();
+    w.register::<CompShape>();
+    // Crates, which land on the floor below and topple off each other
+    let crate_shape = CompShape::Box([16.0, 16.0]);
+    w.create_now().with(CompAABB(crate_shape.bounds([16.0, 16.0], 0.0)))
+      .with(CompPrevAABB(crate_shape.bounds([16.0, 16.0], 0.0)))
+      .with(CompPrevAngle(0.0))
       .with(CompColor([0.0, 1.0, 0.0, 1.0]))
-      .with(CompBody{vel: [0.0, 0.0], acc: [0.5, 0.3], mass: 5.0, flags: BODY_GRAVITY})
+      .with(CompBody{acc: [0.5, 0.3], mass: 5.0, inertia: crate_shape.inertia(5.0), .. Default::default()})
+      .with(crate_shape.clone())
+      .with(CompRegion(None))
+      .build();
+    w.create_now().with(CompAABB(crate_shape.bounds([24.0, -48.0], 0.3)))
+      .with(CompPrevAABB(crate_shape.bounds([24.0, -48.0], 0.3)))
+      .with(CompPrevAngle(0.3))
+      .with(CompColor([0.0, 0.6, 1.0, 1.0]))
+      .with(CompBody{mass: 2.0, angle: 0.3, inertia: crate_shape.inertia(2.0), .. Default::default()})
+      .with(crate_shape)
       .build();
+    // A static floor for the crates to land on
+    w.create_now().with(CompAABB([0.0, 200.0, 400.0, 16.0]))
+      .with(CompColor([0.5, 0.5, 0.5, 1.0]))
+      .with(CompBody{body_type: BodyType::Static, flags: 0, .. Default::default()})
+      .build();
+    w.add_resource(physics::Contacts::default());
+    w.add_resource(physics::Gravity::default());
+    map.spawn(&mut w);
+    w.add_resource(terrain::locate::SiteIndex::from_diagram(&map.diagram));
+
+    // Voronoi test data
+    let mut voronoi_sites = vec![];
+    for ii in 0..4 {
+      for jj in 0..4 {
+        voronoi_sites.push([100.0 + (ii as f32) * 50.0, 100.0 + (jj as f32) * 50.0 + ii as f32]);
+      }
+    }
+    w.add_resource(terrain::diagram::TerrainDiagram::new(voronoi_sites, None));
     specs::Planner::new(w)
   };
 
   let mut renderer = renderer::Renderer::new(&display);
 
   planner.add_system::<renderer::SysRenderer>(renderer::SysRenderer::new(&renderer), "render", 0);
-  planner.add_system::<physics::RigidBody>(physics::RigidBody, "ph_rigid_body", 0);
+  planner.add_system::<physics::RigidBody>(physics::RigidBody::default(), "ph_rigid_body", 0);
+  // Lower priority, so collisions are resolved after bodies move
+  planner.add_system::<physics::Collision>(physics::Collision::default(), "ph_collision", -1);
+  planner.add_system::<terrain::SysLocateRegion>(terrain::SysLocateRegion, "terrain_locate_region", 0);
+  planner.add_system::<terrain::SysTerrain>(terrain::SysTerrain::new(&renderer), "terrain", 0);
 
-  let mut rng = StdRng::new().unwrap();
-  let mut voronoi_sites = vec![];
-  for ii in 0..4 {
-    for jj in 0..4 {
-      voronoi_sites.push([100.0 + (ii as f32) * 50.0, 100.0 + (jj as f32) * 50.0 + ii as f32]);
-    }
-  }
+  // The map's rivers and roads never change, so record their geometry once
+  // and re-send it each frame
+  let map_controller = renderer.get_renderer_controller();
+  let river_geometry = renderer::RendererController::record(|r| {
+    terrain::river::draw_rivers(&map.drainage, &map.mesh, 0.04, r)
+  });
+  let road_geometry = renderer::RendererController::record(|r| terrain::road::draw_roads(&roads, &map, r));
 
   loop {
     // Check input
     for ev in display.poll_events() {
-      match ev {
-        Event::Closed => return,
-          _ => ()
-      }
+      if let Event::Closed = ev { return }
     }
 
     // Calculate frame delta, store in global state object
     global_state.delta = time::precise_time_ns() - global_state.prev_time;
     global_state.prev_time = time::precise_time_ns();
 
-    // Dispatch ECS with the global state object
+    // Run as many fixed physics steps as the frame delta covers, then
+    // dispatch the frame itself
+    for _ in 0..timestep.advance(global_state.delta) {
+      planner.dispatch(GlobalState {
+        stage: Stage::Physics, delta: timestep.step_ns(), .. global_state.clone()
+      });
+    }
+    global_state.alpha = timestep.alpha();
     planner.dispatch(global_state.clone());
     planner.wait();
 
-    // Add voronoi points test data
-    terrain::voronoi::voronoi(&voronoi_sites, renderer.get_renderer_controller());
+    // Draw the map's rivers and roads on top of its regions
+    map_controller.vertices(river_geometry.clone());
+    map_controller.vertices(road_geometry.clone());
 
     // Receive any vertex data sent by the ECS
     renderer.recv_data();
@@ -0,0 +1,297 @@
+//! A module containing broad phases for collision detection. A broad phase
+//! cheaply finds the pairs of AABBs which overlap, so the narrow phase doesn't
+//! need to check every pair of bodies.
+//!
+//! Broad phases are maintained incrementally - each frame, bodies which moved
+//! are updated and bodies which were deleted are removed, rather than
+//! rebuilding the structure from scratch. Which broad phase works best depends
+//! on the scene:
+//! * `SpatialHash` - A uniform grid. Best when bodies are of similar size and
+//!   spread over a large area.
+//! * `SweepAndPrune` - Bodies sorted along the X axis. Best when bodies vary
+//!   in size, and when they move coherently, so the order rarely changes.
+
+use std::collections::HashMap;
+
+/// A structure tracking the AABBs of a set of proxies, each with a unique ID
+/// (e.g. an entity ID), which can find the pairs of proxies which overlap.
+pub trait BroadPhase: Send {
+  /// Inserts a proxy, or moves it if it's already present.
+  /// # Params
+  /// * `id` - The proxy's ID
+  /// * `aabb` - The proxy's AABB - X, Y, W, H
+  fn update(&mut self, id: usize, aabb: &[f32; 4]);
+
+  /// Removes a proxy. Does nothing if it isn't present.
+  fn remove(&mut self, id: usize);
+
+  /// Finds every pair of proxies whose AABBs overlap.
+  /// # Params
+  /// * `out` - A vector to push the pairs to. Each pair is pushed once, with
+  ///   the lower ID first.
+  fn pairs(&mut self, out: &mut Vec<(usize, usize)>);
+}
+
+/// Checks whether 2 AABBs overlap. AABBs which only touch don't overlap, and
+/// AABBs containing NaN never overlap anything.
+fn overlaps(a: &[f32; 4], b: &[f32; 4]) -> bool {
+  a[0] < b[0] + b[2] && b[0] < a[0] + a[2] && a[1] < b[1] + b[3] && b[1] < a[1] + a[3]
+}
+
+fn has_nan(aabb: &[f32; 4]) -> bool {
+  aabb.iter().any(|x| x.is_nan())
+}
+
+/// Proxies covering more grid cells than this are kept out of a spatial
+/// hash's grid, and checked against every other proxy instead.
+const MAX_PROXY_CELLS : f64 = 4096.0;
+
+/// A proxy in a spatial hash.
+#[derive(Clone, Debug)]
+struct HashProxy {
+  aabb: [f32; 4],
+  /// The range of cells covered by the AABB - min X, min Y, max X, max Y,
+  /// inclusive - or `None` if the proxy isn't in the grid
+  cells: Option<[i32; 4]>,
+}
+
+/// A broad phase which puts proxies into the cells of a uniform grid they
+/// cover. Only proxies sharing a cell are checked against each other. Cells
+/// are stored in a hash map, so the grid is unbounded.
+///
+/// Proxies which would cover a huge number of cells, or whose AABBs are
+/// infinite or NaN, aren't put in the grid. They're checked against every
+/// other proxy instead.
+#[derive(Clone, Debug)]
+pub struct SpatialHash {
+  cell_size: f32,
+  cells: HashMap<(i32, i32), Vec<usize>>,
+  proxies: Vec<Option<HashProxy>>,
+  /// The proxies which aren't in the grid
+  large: Vec<usize>,
+}
+
+impl SpatialHash {
+  /// Creates an empty spatial hash.
+  /// # Params
+  /// * `cell_size` - The width and height of each grid cell. Works best a
+  ///   little larger than a typical body.
+  pub fn new(cell_size: f32) -> SpatialHash {
+    SpatialHash { cell_size, cells: HashMap::new(), proxies: Vec::new(), large: Vec::new() }
+  }
+
+  /// Gets the range of cells an AABB covers, or `None` if it shouldn't be put
+  /// in the grid.
+  fn cell_range(&self, aabb: &[f32; 4]) -> Option<[i32; 4]> {
+    let s = self.cell_size as f64;
+    let (x, y) = (aabb[0] as f64, aabb[1] as f64);
+    let r = [(x / s).floor(), (y / s).floor(), ((x + aabb[2] as f64) / s).floor(), ((y + aabb[3] as f64) / s).floor()];
+    if !r.iter().all(|v| v.is_finite()) { return None; }
+    let count = (r[2] - r[0] + 1.0).max(0.0) * (r[3] - r[1] + 1.0).max(0.0);
+    if count > MAX_PROXY_CELLS { return None; }
+    Some([r[0] as i32, r[1] as i32, r[2] as i32, r[3] as i32])
+  }
+
+  fn insert_cells(&mut self, id: usize, r: &[i32; 4]) {
+    for x in r[0]..r[2] + 1 {
+      for y in r[1]..r[3] + 1 {
+        self.cells.entry((x, y)).or_default().push(id);
+      }
+    }
+  }
+
+  fn remove_cells(&mut self, id: usize, r: &[i32; 4]) {
+    for x in r[0]..r[2] + 1 {
+      for y in r[1]..r[3] + 1 {
+        let empty = match self.cells.get_mut(&(x, y)) {
+          Some(cell) => {
+            cell.retain(|&other| other != id);
+            cell.is_empty()
+          }
+          None => false,
+        };
+        if empty { self.cells.remove(&(x, y)); }
+      }
+    }
+  }
+}
+
+impl BroadPhase for SpatialHash {
+  fn update(&mut self, id: usize, aabb: &[f32; 4]) {
+    let cells = self.cell_range(aabb);
+    if id >= self.proxies.len() { self.proxies.resize(id + 1, None); }
+    let old = self.proxies[id].as_ref().map(|p| p.cells);
+    // Most of the time a proxy stays in the same cells
+    if old != Some(cells) {
+      match old {
+        Some(Some(old)) => self.remove_cells(id, &old),
+        Some(None) => self.large.retain(|&other| other != id),
+        None => {}
+      }
+      match cells {
+        Some(cells) => self.insert_cells(id, &cells),
+        None => self.large.push(id),
+      }
+    }
+    self.proxies[id] = Some(HashProxy { aabb: *aabb, cells });
+  }
+
+  fn remove(&mut self, id: usize) {
+    if let Some(p) = self.proxies.get_mut(id).and_then(|p| p.take()) {
+      match p.cells {
+        Some(cells) => self.remove_cells(id, &cells),
+        None => self.large.retain(|&other| other != id),
+      }
+    }
+  }
+
+  fn pairs(&mut self, out: &mut Vec<(usize, usize)>) {
+    for (&(x, y), cell) in &self.cells {
+      for ii in 0..cell.len() {
+        for jj in ii + 1..cell.len() {
+          let (a, b) = (cell[ii], cell[jj]);
+          let (pa, pb) = (self.proxies[a].as_ref().unwrap(), self.proxies[b].as_ref().unwrap());
+          let (ca, cb) = (pa.cells.unwrap(), pb.cells.unwrap());
+          // Pairs sharing several cells are only reported from the first
+          // cell they share, so they're never reported twice
+          if x != ca[0].max(cb[0]) || y != ca[1].max(cb[1]) { continue; }
+          if overlaps(&pa.aabb, &pb.aabb) { out.push((a.min(b), a.max(b))); }
+        }
+      }
+    }
+    for &a in &self.large {
+      let pa = self.proxies[a].as_ref().unwrap();
+      for (b, pb) in self.proxies.iter().enumerate() {
+        let pb = match *pb { Some(ref pb) => pb, None => continue };
+        // Pairs of proxies outside the grid are only checked once
+        if b == a || (pb.cells.is_none() && b < a) { continue; }
+        if overlaps(&pa.aabb, &pb.aabb) { out.push((a.min(b), a.max(b))); }
+      }
+    }
+  }
+}
+
+/// A broad phase which keeps proxies sorted by their left edge. Sweeping
+/// along the sorted list, each proxy is only checked against the proxies
+/// which start before it ends. The list is re-sorted with an insertion sort,
+/// which is close to linear time when bodies move a little each frame.
+#[derive(Clone, Debug, Default)]
+pub struct SweepAndPrune {
+  /// The AABBs of the proxies, indexed by ID
+  aabbs: Vec<Option<[f32; 4]>>,
+  /// The IDs of the proxies, sorted by the left edges of their AABBs.
+  /// Proxies with NaN in their AABBs can't overlap anything, and can't be
+  /// sorted, so they're left out.
+  sorted: Vec<usize>,
+}
+
+impl SweepAndPrune {
+  /// Creates an empty sweep and prune broad phase.
+  pub fn new() -> SweepAndPrune {
+    Default::default()
+  }
+}
+
+impl BroadPhase for SweepAndPrune {
+  fn update(&mut self, id: usize, aabb: &[f32; 4]) {
+    if id >= self.aabbs.len() { self.aabbs.resize(id + 1, None); }
+    let was_sorted = self.aabbs[id].map_or(false, |a| !has_nan(&a));
+    match (was_sorted, !has_nan(aabb)) {
+      (false, true) => self.sorted.push(id),
+      (true, false) => self.sorted.retain(|&other| other != id),
+      _ => {}
+    }
+    self.aabbs[id] = Some(*aabb);
+  }
+
+  fn remove(&mut self, id: usize) {
+    if self.aabbs.get_mut(id).and_then(|a| a.take()).map_or(false, |a| !has_nan(&a)) {
+      self.sorted.retain(|&other| other != id);
+    }
+  }
+
+  fn pairs(&mut self, out: &mut Vec<(usize, usize)>) {
+    let aabbs = &self.aabbs;
+    let left = |id: usize| aabbs[id].unwrap()[0];
+
+    // Insertion sort
+    for ii in 1..self.sorted.len() {
+      let mut jj = ii;
+      while jj > 0 && left(self.sorted[jj - 1]) > left(self.sorted[jj]) {
+        self.sorted.swap(jj - 1, jj);
+        jj -= 1;
+      }
+    }
+
+    // Sweep
+    for (ii, &a) in self.sorted.iter().enumerate() {
+      let aabb_a = aabbs[a].unwrap();
+      let right = aabb_a[0] + aabb_a[2];
+      for &b in &self.sorted[ii + 1..] {
+        let aabb_b = aabbs[b].unwrap();
+        if aabb_b[0] >= right { break; }
+        if overlaps(&aabb_a, &aabb_b) { out.push((a.min(b), a.max(b))); }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::Rng;
+  use terrain::seeded_rng;
+
+  /// Finds the overlapping pairs of a broad phase, sorted.
+  fn sorted_pairs<B: BroadPhase>(b: &mut B) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    b.pairs(&mut out);
+    out.sort();
+    out
+  }
+
+  #[test]
+  fn matches_brute_force() {
+    let mut rng = seeded_rng(19);
+    let (mut hash, mut sap) = (SpatialHash::new(16.0), SweepAndPrune::new());
+    let mut aabbs : Vec<Option<[f32; 4]>> = vec![None; 200];
+    for round in 0..20 {
+      // Add, move and remove random proxies, including some huge, infinite
+      // and NaN ones
+      for _ in 0..100 {
+        let id = rng.gen_range(0, aabbs.len());
+        let aabb = match rng.gen_range(0, 40) {
+          0 => None,
+          1 => Some([rng.gen_range(-100.0, 100.0), 0.0, f32::INFINITY, 50.0]),
+          2 => Some([f32::NEG_INFINITY, rng.gen_range(-100.0, 100.0), f32::INFINITY, 10.0]),
+          3 => Some([f32::NAN, 0.0, 10.0, 10.0]),
+          4 => Some([-1e30, -1e30, 2e30, 2e30]),
+          _ => Some([rng.gen_range(-200.0, 200.0), rng.gen_range(-200.0, 200.0),
+                     rng.gen_range(0.0, 40.0), rng.gen_range(0.0, 40.0)]),
+        };
+        match aabb {
+          Some(aabb) => {
+            hash.update(id, &aabb);
+            sap.update(id, &aabb);
+          }
+          None => {
+            hash.remove(id);
+            sap.remove(id);
+          }
+        }
+        aabbs[id] = aabb;
+      }
+
+      let mut expected = Vec::new();
+      for a in 0..aabbs.len() {
+        for b in a + 1..aabbs.len() {
+          if let (Some(aa), Some(ab)) = (aabbs[a], aabbs[b]) {
+            if overlaps(&aa, &ab) { expected.push((a, b)); }
+          }
+        }
+      }
+      assert_eq!(sorted_pairs(&mut hash), expected, "spatial hash, round {}", round);
+      assert_eq!(sorted_pairs(&mut sap), expected, "sweep and prune, round {}", round);
+    }
+  }
+}
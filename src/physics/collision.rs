@@ -0,0 +1,304 @@
+//! A module for detecting and resolving collisions between rigid bodies.
+//!
+//! Every frame, overlapping pairs of bodies are found and a contact is made
+//! for each, with the normal and depth of the overlap and the points where
+//! the bodies touch. Contacts are resolved with impulses at each point, which
+//! stop the bodies moving into each other and apply friction. Impulses away
+//! from a body's center also spin it, so boxes can tip over. Then any
+//! remaining overlap is corrected by pushing the bodies apart.
+//!
+//! Static and kinematic bodies have infinite mass, so they're never moved by
+//! collisions.
+
+use specs;
+use std::collections::HashMap;
+use component::*;
+use state::{GlobalState, Stage};
+use physics::broad_phase::{BroadPhase, SpatialHash};
+use physics::narrow_phase::{self, Collider};
+
+/// A contact between 2 overlapping bodies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contact {
+  pub a: specs::Entity,
+  pub b: specs::Entity,
+  /// The direction to push `b` to separate it from `a`, as a unit vector
+  pub normal: [f32; 2],
+  /// How far the bodies overlap along the normal
+  pub depth: f32,
+  /// The points where the bodies touch, in world space
+  pub points: Vec<[f32; 2]>,
+}
+
+/// A world resource containing the contacts found in the last frame, so other
+/// systems can check e.g. whether a body is standing on something.
+#[derive(Clone, Debug, Default)]
+pub struct Contacts(pub Vec<Contact>);
+
+/// The ECS system which detects and resolves collisions between entities with
+/// a `CompAABB` and a `CompBody`. Entities collide as their `CompShape` if
+/// they have one, and as their `CompAABB` otherwise. Requires a `Contacts`
+/// resource in the world. This should run after `RigidBody`, and only runs in
+/// the physics stage.
+///
+/// Candidate pairs are found with a broad phase, `B`, which is kept up to
+/// date as entities move.
+#[derive(Clone)]
+pub struct Collision<B: BroadPhase = SpatialHash> {
+  /// How bouncy collisions are, from 0 (objects stop dead) to 1 (no energy
+  /// is lost)
+  pub restitution: f32,
+  /// The closing speed below which bodies don't bounce at all, so resting
+  /// bodies don't bounce from the speed they gain under gravity each step
+  pub bounce_threshold: f32,
+  /// The coefficient of friction between bodies
+  pub friction: f32,
+  /// The fraction of the remaining overlap corrected each frame
+  pub correction: f32,
+  /// Overlap allowed without correction, which stops resting bodies jittering
+  pub slop: f32,
+  /// The number of times to resolve every contact's impulse each frame. More
+  /// iterations make stacks more stable.
+  pub iterations: usize,
+  /// The broad phase, with a proxy for each body, keyed by entity ID
+  pub broad_phase: B,
+  /// Whether each entity ID had a proxy in the broad phase after the last
+  /// frame, so proxies of deleted entities can be removed
+  tracked: Vec<bool>,
+  /// The impulses applied at each contact point last step, by pair of
+  /// entities
+  impulses: HashMap<(specs::Entity, specs::Entity), Vec<CachedImpulse>>,
+}
+
+/// The impulses applied at a contact point, kept for the next step.
+#[derive(Clone, Debug)]
+struct CachedImpulse {
+  point: [f32; 2],
+  normal: f32,
+  tangent: f32,
+}
+
+impl<B: BroadPhase> Collision<B> {
+  /// Creates a collision system with the default settings, using the given
+  /// broad phase.
+  pub fn new(broad_phase: B) -> Collision<B> {
+    Collision {
+      restitution: 0.2, bounce_threshold: 1.0, friction: 0.5, correction: 0.8, slop: 0.01, iterations: 8,
+      broad_phase, tracked: Vec::new(), impulses: HashMap::new(),
+    }
+  }
+}
+
+impl Default for Collision {
+  fn default() -> Collision {
+    Collision::new(SpatialHash::new(64.0))
+  }
+}
+
+/// The state of a body while collisions are being resolved.
+struct Body {
+  entity: specs::Entity,
+  aabb: [f32; 4],
+  /// The point the body rotates about - its centroid if it has a shape, or
+  /// else the center of its AABB
+  center: [f32; 2],
+  collider: Collider,
+  vel: [f32; 2],
+  ang_vel: f32,
+  inv_mass: f32,
+  inv_inertia: f32,
+}
+
+impl Body {
+  /// Gets the velocity of a point on the body, given relative to its center.
+  fn point_vel(&self, r: [f32; 2]) -> [f32; 2] {
+    [self.vel[0] - self.ang_vel * r[1], self.vel[1] + self.ang_vel * r[0]]
+  }
+
+  /// Applies an impulse at a point on the body, given relative to its center.
+  fn apply_impulse(&mut self, r: [f32; 2], p: [f32; 2]) {
+    self.vel[0] += p[0] * self.inv_mass;
+    self.vel[1] += p[1] * self.inv_mass;
+    self.ang_vel += cross(r, p) * self.inv_inertia;
+  }
+}
+
+fn cross(a: [f32; 2], b: [f32; 2]) -> f32 { a[0] * b[1] - a[1] * b[0] }
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 { a[0] * b[0] + a[1] * b[1] }
+
+/// How close a contact point must be to one from the last step to be
+/// treated as the same point.
+const WARM_START_DIST : f32 = 1.0;
+
+/// A point where 2 bodies touch, while its impulses are being resolved.
+struct ContactPoint {
+  /// The indices of the bodies
+  a: usize,
+  b: usize,
+  point: [f32; 2],
+  normal: [f32; 2],
+  /// The point relative to each body's center
+  ra: [f32; 2],
+  rb: [f32; 2],
+  /// The inverse of the effective mass along the normal and the tangent
+  normal_mass: f32,
+  tangent_mass: f32,
+  /// The speed to separate at, for restitution
+  bounce: f32,
+  /// The impulses applied so far this step
+  normal_impulse: f32,
+  tangent_impulse: f32,
+}
+
+impl<B: BroadPhase> specs::System<GlobalState> for Collision<B> {
+  fn run(&mut self, arg: specs::RunArg, g: GlobalState) {
+    if g.stage != Stage::Physics { arg.fetch(|_| ()); return; }
+    let (entities, mut all_aabb, mut all_body, all_shape, mut contacts) = arg.fetch(|w| {
+      (w.entities(), w.write::<CompAABB>(), w.write::<CompBody>(), w.read::<CompShape>(),
+       w.write_resource::<Contacts>())
+    });
+
+    use specs::Join;
+    let mut bodies : Vec<Body> = (&entities, &all_aabb, &all_body).join().map(|(entity, aabb, body)| {
+      let (center, collider, inv_inertia) = match all_shape.get(entity) {
+        Some(shape) => {
+          let center = shape.centroid_in(&aabb.0, body.angle);
+          (center, Collider::from_shape(shape, center, body.angle), body.inv_inertia())
+        }
+        // Without a shape, bodies can't rotate
+        None => {
+          let center = [aabb.0[0] + aabb.0[2] / 2.0, aabb.0[1] + aabb.0[3] / 2.0];
+          (center, Collider::from_aabb(&aabb.0), 0.0)
+        }
+      };
+      Body {
+        entity, aabb: aabb.0, center, collider, vel: body.vel, ang_vel: body.ang_vel,
+        inv_mass: body.inv_mass(), inv_inertia,
+      }
+    }).collect();
+
+    // Update the broad phase, removing the proxies of deleted entities
+    let mut index = vec![usize::MAX; self.tracked.len()];
+    for (ii, b) in bodies.iter().enumerate() {
+      let id = b.entity.get_id() as usize;
+      if id >= index.len() { index.resize(id + 1, usize::MAX); }
+      index[id] = ii;
+      self.broad_phase.update(id, &b.aabb);
+    }
+    for (id, tracked) in self.tracked.iter().enumerate() {
+      if *tracked && index[id] == usize::MAX { self.broad_phase.remove(id); }
+    }
+    self.tracked = index.iter().map(|&ii| ii != usize::MAX).collect();
+
+    // Find contacts, skipping pairs which both have infinite mass
+    let mut candidates = Vec::new();
+    self.broad_phase.pairs(&mut candidates);
+    // Resolve in a consistent order, whatever order the broad phase uses
+    candidates.sort_unstable();
+    let mut pairs = Vec::new();
+    for (a, b) in candidates {
+      let (ii, jj) = (index[a], index[b]);
+      if bodies[ii].inv_mass + bodies[jj].inv_mass == 0.0 { continue; }
+      if let Some(m) = narrow_phase::collide(&bodies[ii].collider, &bodies[jj].collider) {
+        pairs.push((ii, jj, m));
+      }
+    }
+
+    let mut points = Vec::new();
+    for &(ii, jj, ref m) in &pairs {
+      let (a, b) = (&bodies[ii], &bodies[jj]);
+      let (ca, cb) = (a.center, b.center);
+      let n = m.normal;
+      let t = [-n[1], n[0]];
+      for p in &m.points {
+        let ra = [p[0] - ca[0], p[1] - ca[1]];
+        let rb = [p[0] - cb[0], p[1] - cb[1]];
+        let mass = |dir: [f32; 2]| {
+          let k = a.inv_mass + b.inv_mass
+            + cross(ra, dir).powi(2) * a.inv_inertia + cross(rb, dir).powi(2) * b.inv_inertia;
+          if k > 0.0 { 1.0 / k } else { 0.0 }
+        };
+        let (va, vb) = (a.point_vel(ra), b.point_vel(rb));
+        let closing = dot([vb[0] - va[0], vb[1] - va[1]], n);
+        // Start from the impulses at the same point last step, so resting
+        // contacts don't need to be solved from scratch every step
+        let (normal_impulse, tangent_impulse) = self.impulses.get(&(a.entity, b.entity))
+          .and_then(|old| old.iter().find(|o| {
+            (o.point[0] - p[0]).abs() + (o.point[1] - p[1]).abs() < WARM_START_DIST
+          }))
+          .map_or((0.0, 0.0), |o| (o.normal, o.tangent));
+        points.push(ContactPoint {
+          a: ii, b: jj, point: *p, normal: n, ra, rb, normal_mass: mass(n), tangent_mass: mass(t),
+          bounce: if -closing > self.bounce_threshold { -self.restitution * closing } else { 0.0 },
+          normal_impulse, tangent_impulse,
+        });
+      }
+    }
+    for c in &points {
+      let (n, t) = (c.normal, [-c.normal[1], c.normal[0]]);
+      let p = [n[0] * c.normal_impulse + t[0] * c.tangent_impulse,
+               n[1] * c.normal_impulse + t[1] * c.tangent_impulse];
+      bodies[c.a].apply_impulse(c.ra, [-p[0], -p[1]]);
+      bodies[c.b].apply_impulse(c.rb, p);
+    }
+
+    // Apply impulses to stop the bodies moving towards each other, and to
+    // stop them sliding against each other. The total impulse at each point
+    // is clamped, rather than each iteration's, so later iterations can
+    // undo earlier ones.
+    for _ in 0..self.iterations {
+      for c in &mut points {
+        let n = c.normal;
+        let t = [-n[1], n[0]];
+        let (a, b, ra, rb) = (c.a, c.b, c.ra, c.rb);
+        let rel = |bodies: &[Body]| {
+          let (va, vb) = (bodies[a].point_vel(ra), bodies[b].point_vel(rb));
+          [vb[0] - va[0], vb[1] - va[1]]
+        };
+
+        let vn = dot(rel(&bodies), n);
+        let total = (c.normal_impulse + (c.bounce - vn) * c.normal_mass).max(0.0);
+        let pn = total - c.normal_impulse;
+        c.normal_impulse = total;
+        bodies[a].apply_impulse(ra, [-n[0] * pn, -n[1] * pn]);
+        bodies[b].apply_impulse(rb, [n[0] * pn, n[1] * pn]);
+
+        let vt = dot(rel(&bodies), t);
+        let max_friction = self.friction * c.normal_impulse;
+        let total = (c.tangent_impulse - vt * c.tangent_mass).clamp(-max_friction, max_friction);
+        let pt = total - c.tangent_impulse;
+        c.tangent_impulse = total;
+        bodies[a].apply_impulse(ra, [-t[0] * pt, -t[1] * pt]);
+        bodies[b].apply_impulse(rb, [t[0] * pt, t[1] * pt]);
+      }
+    }
+
+    self.impulses.clear();
+    for c in &points {
+      self.impulses.entry((bodies[c.a].entity, bodies[c.b].entity)).or_default()
+        .push(CachedImpulse { point: c.point, normal: c.normal_impulse, tangent: c.tangent_impulse });
+    }
+
+    // Push the bodies apart, in proportion to their inverse masses
+    for &(ii, jj, ref m) in &pairs {
+      let n = m.normal;
+      let (ia, ib) = (bodies[ii].inv_mass, bodies[jj].inv_mass);
+      let push = (m.depth - self.slop).max(0.0) * self.correction / (ia + ib);
+      bodies[ii].aabb[0] -= n[0] * push * ia;
+      bodies[ii].aabb[1] -= n[1] * push * ia;
+      bodies[jj].aabb[0] += n[0] * push * ib;
+      bodies[jj].aabb[1] += n[1] * push * ib;
+    }
+
+    for b in &bodies {
+      if let Some(aabb) = all_aabb.get_mut(b.entity) { aabb.0 = b.aabb; }
+      if let Some(body) = all_body.get_mut(b.entity) {
+        body.vel = b.vel;
+        body.ang_vel = b.ang_vel;
+      }
+    }
+    contacts.0 = pairs.into_iter().map(|(ii, jj, m)| {
+      Contact { a: bodies[ii].entity, b: bodies[jj].entity, normal: m.normal, depth: m.depth, points: m.points }
+    }).collect();
+  }
+}
@@ -0,0 +1,66 @@
+//! A module for applying forces to bodies from any thread.
+//!
+//! Systems which run in the physics stage before `RigidBody` can apply forces
+//! straight to a `CompBody`. Anything else - systems in the frame stage, or
+//! other threads - should use a `ForceController`, which sends the force to
+//! `RigidBody` to apply at the start of its next step, so it never races the
+//! integrator.
+
+use specs;
+use std::sync::mpsc;
+
+/// A force or impulse sent to `RigidBody` through a `ForceController`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForceCommand {
+  /// A force through the body's center
+  Force(specs::Entity, [f32; 2]),
+  /// A force at a point in world coordinates
+  ForceAtPoint(specs::Entity, [f32; 2], [f32; 2]),
+  /// An impulse through the body's center
+  Impulse(specs::Entity, [f32; 2]),
+}
+
+/// This struct wraps a Sender<ForceCommand>, and has convenience methods to
+/// apply forces to bodies. Forces apply for a single physics step, so a
+/// continuous force applied once a frame should be an impulse of the force
+/// multiplied by the frame delta instead, to be independent of the frame
+/// rate.
+#[derive(Clone, Debug)]
+pub struct ForceController {
+  sender: mpsc::Sender<ForceCommand>,
+}
+
+impl ForceController {
+  /// Creates a new force controller with a given mpsc sender. If you want to
+  /// get a force controller, look at the
+  /// physics::RigidBody::get_force_controller() function.
+  pub fn new(sender: mpsc::Sender<ForceCommand>) -> ForceController {
+    ForceController { sender }
+  }
+
+  /// Applies a force through an entity's center for the next physics step.
+  pub fn apply_force(&self, e: specs::Entity, force: [f32; 2]) {
+    self.send(ForceCommand::Force(e, force));
+  }
+
+  /// Applies a force at a point for the next physics step.
+  /// # Params
+  /// * `e` - The entity to apply the force to
+  /// * `force` - The force to apply
+  /// * `point` - Where to apply the force, in world coordinates
+  pub fn apply_force_at_point(&self, e: specs::Entity, force: [f32; 2], point: [f32; 2]) {
+    self.send(ForceCommand::ForceAtPoint(e, force, point));
+  }
+
+  /// Applies an impulse through an entity's center at the start of the next
+  /// physics step.
+  pub fn apply_impulse(&self, e: specs::Entity, impulse: [f32; 2]) {
+    self.send(ForceCommand::Impulse(e, impulse));
+  }
+
+  fn send(&self, command: ForceCommand) {
+    // The RigidBody system may have been dropped, e.g. when shutting down,
+    // in which case there's nothing to apply the force to
+    let _ = self.sender.send(command);
+  }
+}
@@ -0,0 +1,55 @@
+//! A module for the world's gravity, which may differ from place to place.
+
+/// The area a gravity zone covers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ZoneArea {
+  /// An AABB - X, Y, W, H. Bodies are in it if their center is.
+  AABB([f32; 4]),
+  /// A terrain region (Voronoi cell), by index. Bodies are in it if their
+  /// `CompRegion` is, which is only updated once a frame, by
+  /// `terrain::SysLocateRegion`.
+  Region(usize),
+}
+
+/// An area where gravity is different to the rest of the world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GravityZone {
+  pub area: ZoneArea,
+  /// The gravity in the zone, as an acceleration
+  pub gravity: [f32; 2],
+}
+
+/// A world resource containing the world's gravity. This can be changed at
+/// runtime - `RigidBody` reads it every step.
+#[derive(Clone, Debug)]
+pub struct Gravity {
+  /// The gravity outside of any zone, as an acceleration. Defaults to 9.8
+  /// downwards.
+  pub vector: [f32; 2],
+  /// Zones which override the gravity locally. If zones overlap, the last
+  /// one takes priority, so smaller zones can be put inside larger ones.
+  pub zones: Vec<GravityZone>,
+}
+
+impl Gravity {
+  /// Gets the gravity at a point.
+  /// # Params
+  /// * `pos` - The point
+  /// * `region` - The terrain region the point is in, if known
+  /// # Returns
+  /// The gravity at the point, as an acceleration
+  pub fn at(&self, pos: [f32; 2], region: Option<usize>) -> [f32; 2] {
+    let zone = self.zones.iter().rev().find(|z| match z.area {
+      ZoneArea::AABB(aabb) => pos[0] >= aabb[0] && pos[0] < aabb[0] + aabb[2]
+                           && pos[1] >= aabb[1] && pos[1] < aabb[1] + aabb[3],
+      ZoneArea::Region(r) => region == Some(r),
+    });
+    zone.map_or(self.vector, |z| z.gravity)
+  }
+}
+
+impl Default for Gravity {
+  fn default() -> Gravity {
+    Gravity { vector: [0.0, 9.8], zones: Vec::new() }
+  }
+}
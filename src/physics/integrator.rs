@@ -0,0 +1,132 @@
+//! A module containing numerical integrators, which advance a body's position
+//! and velocity through time given its acceleration.
+
+/// A scheme for integrating a body's motion. Schemes differ in accuracy, cost
+/// and how well they conserve energy over many steps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+  /// Updates velocity, then position with the new velocity. First order, but
+  /// symplectic, so energy oscillates rather than drifting. 1 acceleration
+  /// evaluation per step.
+  SemiImplicitEuler,
+  /// Second order and symplectic - a good default. 2 acceleration
+  /// evaluations per step.
+  #[default]
+  VelocityVerlet,
+  /// Classic 4th order Runge-Kutta. Very accurate over short times, but not
+  /// symplectic, so energy slowly drifts. 4 acceleration evaluations per
+  /// step.
+  Rk4,
+}
+
+fn add(a: [f32; 2], b: [f32; 2], s: f32) -> [f32; 2] {
+  [a[0] + b[0] * s, a[1] + b[1] * s]
+}
+
+impl Integrator {
+  /// Advances a body by one step.
+  /// # Params
+  /// * `pos` - The body's position
+  /// * `vel` - The body's velocity
+  /// * `dt` - The length of the step in seconds
+  /// * `acc` - A function giving the body's acceleration, given its position
+  ///   and velocity
+  /// # Returns
+  /// The body's new position and velocity
+  pub fn step<F>(&self, pos: [f32; 2], vel: [f32; 2], dt: f32, acc: F) -> ([f32; 2], [f32; 2])
+    where F: Fn([f32; 2], [f32; 2]) -> [f32; 2] {
+    match *self {
+      Integrator::SemiImplicitEuler => {
+        let vel = add(vel, acc(pos, vel), dt);
+        (add(pos, vel, dt), vel)
+      }
+      Integrator::VelocityVerlet => {
+        let a0 = acc(pos, vel);
+        let pos = add(add(pos, vel, dt), a0, dt * dt / 2.0);
+        // Velocity dependent accelerations are evaluated with an estimate of
+        // the new velocity
+        let a1 = acc(pos, add(vel, a0, dt));
+        (pos, add(vel, [a0[0] + a1[0], a0[1] + a1[1]], dt / 2.0))
+      }
+      Integrator::Rk4 => {
+        let (x1, v1) = (pos, vel);
+        let a1 = acc(x1, v1);
+        let (x2, v2) = (add(pos, v1, dt / 2.0), add(vel, a1, dt / 2.0));
+        let a2 = acc(x2, v2);
+        let (x3, v3) = (add(pos, v2, dt / 2.0), add(vel, a2, dt / 2.0));
+        let a3 = acc(x3, v3);
+        let (x4, v4) = (add(pos, v3, dt), add(vel, a3, dt));
+        let a4 = acc(x4, v4);
+        let sum = |a: [f32; 2], b: [f32; 2], c: [f32; 2], d: [f32; 2]| {
+          [a[0] + 2.0 * (b[0] + c[0]) + d[0], a[1] + 2.0 * (b[1] + c[1]) + d[1]]
+        };
+        (add(pos, sum(v1, v2, v3, v4), dt / 6.0), add(vel, sum(a1, a2, a3, a4), dt / 6.0))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ALL: [Integrator; 3] = [Integrator::SemiImplicitEuler, Integrator::VelocityVerlet, Integrator::Rk4];
+
+  /// Runs a simulation, returning the largest relative error in energy seen.
+  fn max_energy_drift<F, E>(integrator: Integrator, pos: [f32; 2], vel: [f32; 2], dt: f32, steps: usize,
+                            acc: F, energy: E) -> f32
+    where F: Fn([f32; 2], [f32; 2]) -> [f32; 2], E: Fn([f32; 2], [f32; 2]) -> f32 {
+    let e0 = energy(pos, vel);
+    let (mut pos, mut vel) = (pos, vel);
+    let mut max_drift = 0.0f32;
+    for _ in 0..steps {
+      let next = integrator.step(pos, vel, dt, &acc);
+      pos = next.0;
+      vel = next.1;
+      max_drift = max_drift.max(((energy(pos, vel) - e0) / e0).abs());
+    }
+    max_drift
+  }
+
+  #[test]
+  fn spring_energy() {
+    // A unit mass on a spring with stiffness 4, for about 30 periods
+    let k = 4.0;
+    let acc = |p: [f32; 2], _| [-k * p[0], -k * p[1]];
+    let energy = |p: [f32; 2], v: [f32; 2]| {
+      0.5 * (v[0] * v[0] + v[1] * v[1]) + 0.5 * k * (p[0] * p[0] + p[1] * p[1])
+    };
+    for &(integrator, tolerance) in &[(ALL[0], 0.02), (ALL[1], 1e-3), (ALL[2], 2e-5)] {
+      let drift = max_energy_drift(integrator, [1.0, 0.0], [0.0, 0.5], 0.01, 10000, acc, energy);
+      assert!(drift < tolerance, "{:?} drifted by {}", integrator, drift);
+    }
+  }
+
+  #[test]
+  fn orbit_energy() {
+    // A circular orbit of radius 1 around a unit mass at the origin, for
+    // about 10 orbits
+    let acc = |p: [f32; 2], _| {
+      let r = (p[0] * p[0] + p[1] * p[1]).sqrt();
+      [-p[0] / (r * r * r), -p[1] / (r * r * r)]
+    };
+    let energy = |p: [f32; 2], v: [f32; 2]| {
+      0.5 * (v[0] * v[0] + v[1] * v[1]) - 1.0 / (p[0] * p[0] + p[1] * p[1]).sqrt()
+    };
+    for &(integrator, tolerance) in &[(ALL[0], 1e-3), (ALL[1], 1e-4), (ALL[2], 1e-4)] {
+      let drift = max_energy_drift(integrator, [1.0, 0.0], [0.0, 1.0], 0.01, 6300, acc, energy);
+      assert!(drift < tolerance, "{:?} drifted by {}", integrator, drift);
+    }
+  }
+
+  #[test]
+  fn constant_acceleration_is_exact() {
+    // Under constant acceleration, Verlet and RK4 should match the exact
+    // solution, as the old RigidBody update did
+    for &integrator in &ALL[1..] {
+      let (pos, vel) = integrator.step([1.0, 2.0], [3.0, -4.0], 0.5, |_, _| [0.0, 9.8]);
+      assert!((pos[0] - 2.5).abs() < 1e-5 && (pos[1] - (2.0 - 2.0 + 1.225)).abs() < 1e-5);
+      assert!((vel[0] - 3.0).abs() < 1e-5 && (vel[1] - (-4.0 + 4.9)).abs() < 1e-5);
+    }
+  }
+}
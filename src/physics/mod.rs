@@ -2,6 +2,18 @@
 //! game world.
 
 mod rigid_body;
+mod collision;
+pub mod broad_phase;
+pub mod narrow_phase;
+mod timestep;
+mod integrator;
+pub mod force;
+pub mod gravity;
 
 pub use self::rigid_body::RigidBody;
+pub use self::collision::{Collision, Contact, Contacts};
+pub use self::timestep::FixedTimestep;
+pub use self::integrator::Integrator;
+pub use self::force::ForceController;
+pub use self::gravity::Gravity;
 
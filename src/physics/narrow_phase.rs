@@ -0,0 +1,177 @@
+//! A module for the narrow phase of collision detection, which finds exactly
+//! where 2 shapes touch.
+//!
+//! Colliding shapes are found with the separating axis theorem (SAT) - 2
+//! convex shapes overlap unless some axis separates them, and the axis with
+//! the least overlap gives the contact normal and depth. For polygons, the
+//! contact points are found by clipping the edge of one polygon against the
+//! face of the other, which gives 2 points when boxes rest flat on each other,
+//! so stacks are stable.
+
+use component::CompShape;
+
+/// A shape in world space, ready for collision detection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Collider {
+  /// A circle, given its center and radius
+  Circle([f32; 2], f32),
+  /// A convex polygon, counter-clockwise (positive signed area)
+  Polygon(Vec<[f32; 2]>),
+}
+
+impl Collider {
+  /// Creates a collider from a shape.
+  /// # Params
+  /// * `shape` - The shape
+  /// * `center` - The position of the shape's centroid
+  /// * `angle` - The rotation of the shape in radians
+  pub fn from_shape(shape: &CompShape, center: [f32; 2], angle: f32) -> Collider {
+    match *shape {
+      CompShape::Circle(r) => Collider::Circle(center, r),
+      _ => Collider::Polygon(shape.outline(center, angle)),
+    }
+  }
+
+  /// Creates a collider from an AABB - X, Y, W, H.
+  pub fn from_aabb(aabb: &[f32; 4]) -> Collider {
+    let (x0, y0, x1, y1) = (aabb[0], aabb[1], aabb[0] + aabb[2], aabb[1] + aabb[3]);
+    Collider::Polygon(vec![[x0, y0], [x1, y0], [x1, y1], [x0, y1]])
+  }
+}
+
+/// Where 2 shapes touch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifold {
+  /// The direction to push the 2nd shape to separate it from the 1st, as a
+  /// unit vector
+  pub normal: [f32; 2],
+  /// How far the shapes overlap along the normal
+  pub depth: f32,
+  /// The points where the shapes touch, in world space - 1 or 2 of them
+  pub points: Vec<[f32; 2]>,
+}
+
+/// Finds where 2 colliders touch, if they do.
+/// # Returns
+/// The contact manifold, with the normal pointing from `a` to `b`, or `None`
+/// if the colliders don't overlap.
+pub fn collide(a: &Collider, b: &Collider) -> Option<Manifold> {
+  match (a, b) {
+    (Collider::Polygon(pa), Collider::Polygon(pb)) => polygons(pa, pb),
+    (Collider::Polygon(pa), &Collider::Circle(c, r)) => circle_polygon(pa, c, r),
+    (&Collider::Circle(c, r), Collider::Polygon(pb)) => circle_polygon(pb, c, r).map(|mut m| {
+      m.normal = [-m.normal[0], -m.normal[1]];
+      m
+    }),
+    (&Collider::Circle(ca, ra), &Collider::Circle(cb, rb)) => circles(ca, ra, cb, rb),
+  }
+}
+
+fn sub(a: [f32; 2], b: [f32; 2]) -> [f32; 2] { [a[0] - b[0], a[1] - b[1]] }
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 { a[0] * b[0] + a[1] * b[1] }
+fn len(a: [f32; 2]) -> f32 { dot(a, a).sqrt() }
+
+/// Gets the outward unit normal of edge `ii` of a counter-clockwise polygon.
+fn edge_normal(poly: &[[f32; 2]], ii: usize) -> [f32; 2] {
+  let e = sub(poly[(ii + 1) % poly.len()], poly[ii]);
+  let l = len(e);
+  [e[1] / l, -e[0] / l]
+}
+
+/// Finds the edge of `a` which best separates `b` from it.
+/// # Returns
+/// The edge's index, and how far `b` is from it - negative if they overlap
+fn max_separation(a: &[[f32; 2]], b: &[[f32; 2]]) -> (usize, f32) {
+  (0..a.len()).map(|ii| {
+    let n = edge_normal(a, ii);
+    let sep = b.iter().map(|&p| dot(n, sub(p, a[ii]))).fold(f32::INFINITY, f32::min);
+    (ii, sep)
+  }).fold((0, f32::NEG_INFINITY), |best, x| if x.1 > best.1 { x } else { best })
+}
+
+/// Clips a segment, keeping the part where `dot(n, p) <= offset`.
+fn clip(seg: &[[f32; 2]], n: [f32; 2], offset: f32) -> Vec<[f32; 2]> {
+  let mut out = Vec::with_capacity(2);
+  let d0 = dot(n, seg[0]) - offset;
+  let d1 = dot(n, seg[1]) - offset;
+  if d0 <= 0.0 { out.push(seg[0]); }
+  if d1 <= 0.0 { out.push(seg[1]); }
+  if d0 * d1 < 0.0 {
+    let t = d0 / (d0 - d1);
+    out.push([seg[0][0] + (seg[1][0] - seg[0][0]) * t, seg[0][1] + (seg[1][1] - seg[0][1]) * t]);
+  }
+  out
+}
+
+fn polygons(a: &[[f32; 2]], b: &[[f32; 2]]) -> Option<Manifold> {
+  let (edge_a, sep_a) = max_separation(a, b);
+  if sep_a > 0.0 { return None; }
+  let (edge_b, sep_b) = max_separation(b, a);
+  if sep_b > 0.0 { return None; }
+
+  // Use the face of whichever polygon separates best as the reference face,
+  // preferring `a` so the choice doesn't flicker between frames
+  let flip = sep_b > sep_a + 1e-3 * (1.0 + sep_a.abs());
+  let (reference, incident, edge) = if flip { (b, a, edge_b) } else { (a, b, edge_a) };
+  let n = edge_normal(reference, edge);
+  let (v1, v2) = (reference[edge], reference[(edge + 1) % reference.len()]);
+
+  // The incident edge is the one facing most against the reference face
+  let inc = (0..incident.len()).map(|ii| (ii, dot(edge_normal(incident, ii), n)))
+    .fold((0, f32::INFINITY), |best, x| if x.1 < best.1 { x } else { best }).0;
+  let seg = [incident[inc], incident[(inc + 1) % incident.len()]];
+
+  // Clip the incident edge to the sides of the reference face
+  let t = { let e = sub(v2, v1); let l = len(e); [e[0] / l, e[1] / l] };
+  let seg = clip(&seg, [-t[0], -t[1]], -dot(t, v1));
+  if seg.len() < 2 { return None; }
+  let seg = clip(&seg, t, dot(t, v2));
+  if seg.len() < 2 { return None; }
+
+  // Keep the points behind the reference face
+  let front = dot(n, v1);
+  let mut depth = 0.0f32;
+  let points : Vec<[f32; 2]> = seg.into_iter().filter(|&p| {
+    let sep = dot(n, p) - front;
+    depth = depth.max(-sep);
+    sep <= 0.0
+  }).collect();
+  if points.is_empty() { return None; }
+  let normal = if flip { [-n[0], -n[1]] } else { n };
+  Some(Manifold { normal, depth, points })
+}
+
+/// Collides a polygon with a circle. The normal points from the polygon to
+/// the circle.
+fn circle_polygon(poly: &[[f32; 2]], c: [f32; 2], r: f32) -> Option<Manifold> {
+  let (edge, sep) = (0..poly.len()).map(|ii| (ii, dot(edge_normal(poly, ii), sub(c, poly[ii]))))
+    .fold((0, f32::NEG_INFINITY), |best, x| if x.1 > best.1 { x } else { best });
+  if sep > r { return None; }
+
+  if sep <= 0.0 {
+    // The center is inside the polygon, so push out through the nearest face
+    let n = edge_normal(poly, edge);
+    return Some(Manifold { normal: n, depth: r - sep, points: vec![[c[0] - n[0] * sep, c[1] - n[1] * sep]] });
+  }
+
+  // Otherwise, find the closest point on the polygon's boundary
+  let (q, dist) = (0..poly.len()).map(|ii| {
+    let (a, b) = (poly[ii], poly[(ii + 1) % poly.len()]);
+    let e = sub(b, a);
+    let t = (dot(sub(c, a), e) / dot(e, e)).clamp(0.0, 1.0);
+    let q = [a[0] + e[0] * t, a[1] + e[1] * t];
+    (q, len(sub(c, q)))
+  }).fold(([0.0; 2], f32::INFINITY), |best, x| if x.1 < best.1 { x } else { best });
+  if dist > r || dist == 0.0 { return None; }
+  let n = sub(c, q);
+  Some(Manifold { normal: [n[0] / dist, n[1] / dist], depth: r - dist, points: vec![q] })
+}
+
+fn circles(ca: [f32; 2], ra: f32, cb: [f32; 2], rb: f32) -> Option<Manifold> {
+  let d = sub(cb, ca);
+  let dist = len(d);
+  if dist >= ra + rb { return None; }
+  // Circles with the same center are pushed apart vertically
+  let normal = if dist > 0.0 { [d[0] / dist, d[1] / dist] } else { [0.0, 1.0] };
+  Some(Manifold { normal, depth: ra + rb - dist, points: vec![[ca[0] + normal[0] * ra, ca[1] + normal[1] * ra]] })
+}
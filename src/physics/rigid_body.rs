@@ -2,27 +2,263 @@
 
 use specs;
 use component::*;
-use state::GlobalState;
+use state::{GlobalState, Stage};
+use physics::Integrator;
+use physics::gravity::Gravity;
+use physics::force::{ForceCommand, ForceController};
+use std::sync::mpsc;
 
-#[derive(Clone)]
-pub struct RigidBody;
+/// The ECS system which moves entities with a `CompAABB` and a `CompBody`.
+/// Requires a `Gravity` resource in the world. Only runs in the physics
+/// stage.
+///
+/// Each step, the forces sent through this system's `ForceController`s are
+/// applied, then bodies are moved by their accumulated forces, and the
+/// forces are cleared.
+pub struct RigidBody {
+  /// The integrator for bodies without a `CompIntegrator`
+  pub integrator: Integrator,
+  force_channel_pair: (mpsc::Sender<ForceCommand>, mpsc::Receiver<ForceCommand>),
+}
+
+impl RigidBody {
+  /// Creates a new rigid body system.
+  /// # Params
+  /// * `integrator` - The integrator for bodies without a `CompIntegrator`
+  pub fn new(integrator: Integrator) -> RigidBody {
+    RigidBody { integrator, force_channel_pair: mpsc::channel() }
+  }
+
+  /// Gets a force controller, which can be used to apply forces to bodies
+  /// from other threads.
+  pub fn get_force_controller(&self) -> ForceController {
+    ForceController::new(self.force_channel_pair.0.clone())
+  }
+}
+
+impl Default for RigidBody {
+  fn default() -> RigidBody {
+    RigidBody::new(Integrator::default())
+  }
+}
 
 impl specs::System<GlobalState> for RigidBody {
   fn run(&mut self, arg: specs::RunArg, g: GlobalState) {
-    let (mut all_aabb, mut all_body) = arg.fetch(|w| {
-      (w.write::<CompAABB>(), w.write::<CompBody>())
+    if g.stage != Stage::Physics { arg.fetch(|_| ()); return; }
+    let ((entities, gravity, mut all_aabb, mut all_body, all_integrator, all_region, all_shape),
+         (mut all_prev, mut all_prev_angle)) = arg.fetch(|w| {
+      ((w.entities(), w.read_resource::<Gravity>(), w.write::<CompAABB>(), w.write::<CompBody>(),
+        w.read::<CompIntegrator>(), w.read::<CompRegion>(), w.read::<CompShape>()),
+       (w.write::<CompPrevAABB>(), w.write::<CompPrevAngle>()))
     });
 
+    // Apply forces sent from other threads
+    while let Ok(command) = self.force_channel_pair.1.try_recv() {
+      match command {
+        ForceCommand::Force(e, force) => {
+          if let Some(body) = all_body.get_mut(e) { body.apply_force(force); }
+        }
+        ForceCommand::ForceAtPoint(e, force, point) => {
+          if let (Some(body), Some(aabb)) = (all_body.get_mut(e), all_aabb.get(e)) {
+            let center = match all_shape.get(e) {
+              Some(shape) => shape.centroid_in(&aabb.0, body.angle),
+              None => [aabb.0[0] + aabb.0[2] / 2.0, aabb.0[1] + aabb.0[3] / 2.0],
+            };
+            body.apply_force_at_point(force, point, center);
+          }
+        }
+        ForceCommand::Impulse(e, impulse) => {
+          if let Some(body) = all_body.get_mut(e) { body.apply_impulse(impulse); }
+        }
+      }
+    }
+
     use specs::Join;
-    for (aabb, body) in (&mut all_aabb, &mut all_body).join() {
-      let d = g.get_delta_in_s();
-      let d2 = d.powi(2);
-      println!("{}", body.flags);
-      let gravity = if body.flags & BODY_GRAVITY > 0 {9.8} else {0.0};
-      aabb.0[0] += body.vel[0]*d + d2*(body.acc[0])/2.0;
-      aabb.0[1] += body.vel[1]*d + d2*(body.acc[1] + gravity)/2.0;
-      body.vel[0] += (body.acc[0])*d;
-      body.vel[1] += (body.acc[1] + gravity)*d;
+    // Remember where bodies were before this step, for interpolation
+    for (prev, aabb) in (&mut all_prev, &all_aabb).join() {
+      prev.0 = aabb.0;
+    }
+    for (prev, body) in (&mut all_prev_angle, &all_body).join() {
+      prev.0 = body.angle;
+    }
+    let d = g.get_delta_in_s();
+    for (e, aabb, body) in (&entities, &mut all_aabb, &mut all_body).join() {
+      // Bodies with a shape are moved by their centroid, and others by the
+      // corner of their AABB
+      let shape = all_shape.get(e);
+      let (mut pos, to_center) = match shape {
+        Some(shape) => (shape.centroid_in(&aabb.0, body.angle), [0.0, 0.0]),
+        None => ([aabb.0[0], aabb.0[1]], [aabb.0[2] / 2.0, aabb.0[3] / 2.0]),
+      };
+      match body.body_type {
+        BodyType::Static => {}
+        BodyType::Kinematic => {
+          pos[0] += body.vel[0] * d;
+          pos[1] += body.vel[1] * d;
+          body.angle += body.ang_vel * d;
+        }
+        BodyType::Dynamic => {
+          let integrator = all_integrator.get(e).map_or(self.integrator, |i| i.0);
+          let region = all_region.get(e).and_then(|r| r.0);
+          let scale = if body.flags & BODY_GRAVITY > 0 { body.gravity_scale } else { 0.0 };
+          let base = [body.acc[0] + body.force[0] * body.inv_mass(),
+                      body.acc[1] + body.force[1] * body.inv_mass()];
+          // Gravity is evaluated wherever the integrator asks for it, so
+          // bodies crossing into gravity zones mid-step are handled accurately
+          let acc = |pos: [f32; 2], _| {
+            if scale == 0.0 { return base; }
+            let grav = gravity.at([pos[0] + to_center[0], pos[1] + to_center[1]], region);
+            [base[0] + grav[0] * scale, base[1] + grav[1] * scale]
+          };
+          let (new_pos, vel) = integrator.step(pos, body.vel, d, acc);
+          pos = new_pos;
+          body.vel = vel;
+          // Rotation uses the same integrator, with the torque held constant
+          // over the step
+          let alpha = body.torque * body.inv_inertia();
+          let (angle, ang_vel) = integrator.step([body.angle, 0.0], [body.ang_vel, 0.0], d,
+                                                 |_, _| [alpha, 0.0]);
+          body.angle = angle[0];
+          body.ang_vel = ang_vel[0];
+        }
+      }
+      match shape {
+        // Fit the AABB around the rotated shape
+        Some(shape) => aabb.0 = shape.bounds(pos, body.angle),
+        None => {
+          aabb.0[0] = pos[0];
+          aabb.0[1] = pos[1];
+        }
+      }
+      body.force = [0.0, 0.0];
+      body.torque = 0.0;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use specs::Gate;
+  use physics::gravity::{GravityZone, ZoneArea};
+
+  const STEP_NS : u64 = 1_000_000_000 / 60;
+
+  /// Creates a world with everything `RigidBody` needs registered, and no
+  /// gravity.
+  fn world() -> specs::World {
+    let mut w = specs::World::new();
+    w.register::<CompAABB>();
+    w.register::<CompPrevAABB>();
+    w.register::<CompPrevAngle>();
+    w.register::<CompBody>();
+    w.register::<CompIntegrator>();
+    w.register::<CompRegion>();
+    w.register::<CompShape>();
+    w.add_resource(Gravity { vector: [0.0, 0.0], zones: Vec::new() });
+    w
+  }
+
+  /// Checks that shapes spin about their centroid, so a spinning triangle's
+  /// center of mass stays still, even though its AABB changes.
+  #[test]
+  fn spinning_shape_centroid_stays_still() {
+    let mut w = world();
+    // A triangle given from an origin outside it
+    let shape = CompShape::Polygon(vec![[20.0, 20.0], [50.0, 20.0], [20.0, 40.0]]);
+    assert_eq!(shape.centroid(), [30.0, 80.0 / 3.0]);
+    // Its inertia is about the centroid, wherever the points are given from
+    let moved = CompShape::Polygon(vec![[-10.0, -20.0 / 3.0], [20.0, -20.0 / 3.0], [-10.0, 40.0 / 3.0]]);
+    assert!((shape.inertia(1.0) - moved.inertia(1.0)).abs() < 1e-3);
+    let centroid = [300.0, 100.0];
+    let e = w.create_now().with(CompAABB(shape.bounds(centroid, 0.0)))
+      .with(CompBody{ang_vel: 2.0, inertia: shape.inertia(1.0), .. Default::default()})
+      .with(shape.clone()).build();
+
+    let mut planner = specs::Planner::new(w);
+    planner.add_system(RigidBody::default(), "ph_rigid_body", 0);
+    let g = GlobalState { delta: STEP_NS, prev_time: 0, seed: 0, stage: Stage::Physics, alpha: 0.0 };
+    for _ in 0..60 {
+      planner.dispatch(g.clone());
+      planner.wait();
+    }
+
+    let w = planner.mut_world();
+    let (aabb, body) = (w.read::<CompAABB>().pass().get(e).unwrap().0, w.read::<CompBody>().pass());
+    let angle = body.get(e).unwrap().angle;
+    assert!((angle - 2.0).abs() < 1e-3);
+    assert!(aabb != shape.bounds(centroid, 0.0));
+    // The centroid is the mean of a triangle's corners
+    let outline = shape.outline(shape.centroid_in(&aabb, angle), angle);
+    for axis in 0..2 {
+      let mean = outline.iter().map(|p| p[axis]).sum::<f32>() / 3.0;
+      assert!((mean - centroid[axis]).abs() < 1e-3, "centroid moved to {:?}", outline);
+    }
+  }
+
+  /// Checks that gravity is evaluated wherever the integrator asks for it
+  /// during a step, rather than once at the start, by moving bodies into a
+  /// gravity zone. The zone starts just past the bodies, so only evaluations
+  /// later in the step see it.
+  #[test]
+  fn gravity_evaluated_within_step() {
+    let mut w = world();
+    w.write_resource::<Gravity>().pass().zones.push(GravityZone {
+      area: ZoneArea::AABB([100.0, -1000.0, 1000.0, 2000.0]), gravity: [0.0, 500.0],
+    });
+    let start = [98.0, 0.0, 2.0, 2.0];
+    let vel = [300.0, 0.0];
+    // One body with the system's integrator, and one with its own
+    let verlet = w.create_now().with(CompAABB(start)).with(CompBody{vel, .. Default::default()}).build();
+    let rk4 = w.create_now().with(CompAABB(start)).with(CompBody{vel, .. Default::default()})
+      .with(CompIntegrator(Integrator::Rk4)).build();
+
+    let mut planner = specs::Planner::new(w);
+    planner.add_system(RigidBody::new(Integrator::VelocityVerlet), "ph_rigid_body", 0);
+    let g = GlobalState { delta: STEP_NS, prev_time: 0, seed: 0, stage: Stage::Physics, alpha: 0.0 };
+    planner.dispatch(g.clone());
+    planner.wait();
+
+    let w = planner.mut_world();
+    let (gravity, all_aabb, all_body) = (w.read_resource::<Gravity>().pass(), w.read::<CompAABB>().pass(),
+                                         w.read::<CompBody>().pass());
+    let acc = |p: [f32; 2], _| gravity.at([p[0] + 1.0, p[1] + 1.0], None);
+    for &(e, integrator) in &[(verlet, Integrator::VelocityVerlet), (rk4, Integrator::Rk4)] {
+      let (pos, vel) = integrator.step([start[0], start[1]], vel, g.get_delta_in_s(), acc);
+      let (aabb, body) = (all_aabb.get(e).unwrap().0, all_body.get(e).unwrap());
+      assert_eq!([aabb[0], aabb[1]], pos);
+      assert_eq!(body.vel, vel);
+      // Gravity only evaluated at the start would miss the zone completely
+      assert!(body.vel[1] > 0.0);
+    }
+  }
+
+  /// Checks that rotation is integrated by the body's integrator.
+  #[test]
+  fn torque_uses_integrator() {
+    let mut w = world();
+    let torqued = |w: &mut specs::World, integrator| {
+      w.create_now().with(CompAABB([0.0, 0.0, 2.0, 2.0]))
+        .with(CompBody{torque: 6.0, inertia: 2.0, .. Default::default()})
+        .with(CompIntegrator(integrator)).build()
+    };
+    let euler = torqued(&mut w, Integrator::SemiImplicitEuler);
+    let verlet = torqued(&mut w, Integrator::VelocityVerlet);
+
+    let mut planner = specs::Planner::new(w);
+    planner.add_system(RigidBody::default(), "ph_rigid_body", 0);
+    let g = GlobalState { delta: STEP_NS, prev_time: 0, seed: 0, stage: Stage::Physics, alpha: 0.0 };
+    planner.dispatch(g.clone());
+    planner.wait();
+
+    // With an angular acceleration of 3, semi-implicit Euler moves by the new
+    // angular velocity, and Verlet is exact
+    let d = g.get_delta_in_s();
+    let all_body = planner.mut_world().read::<CompBody>().pass();
+    for &(e, angle) in &[(euler, 3.0 * d * d), (verlet, 1.5 * d * d)] {
+      let body = all_body.get(e).unwrap();
+      assert!((body.ang_vel - 3.0 * d).abs() < 1e-6);
+      assert!((body.angle - angle).abs() < 1e-7, "angle {} should be {}", body.angle, angle);
     }
   }
 }
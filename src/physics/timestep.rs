@@ -0,0 +1,66 @@
+//! A module for running physics at a fixed timestep, so the simulation
+//! doesn't depend on the frame rate.
+//!
+//! Each frame's delta is added to an accumulator, and physics steps are run
+//! while there's a whole step in it. The time left over is used to
+//! interpolate between the last 2 physics states when rendering.
+
+/// An accumulator for fixed timestep physics.
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+  /// The length of a step in ns
+  step: u64,
+  /// The most steps to run in one frame. If a frame takes longer than this
+  /// many steps (e.g. after a hitch), the extra time is dropped, so the
+  /// simulation slows down rather than spiralling.
+  pub max_steps: u32,
+  /// The time in ns not yet simulated
+  accumulator: u64,
+}
+
+impl FixedTimestep {
+  /// Creates a new fixed timestep.
+  /// # Params
+  /// * `hz` - The number of physics steps per second
+  /// * `max_steps` - The most steps to run in one frame
+  pub fn new(hz: f32, max_steps: u32) -> FixedTimestep {
+    FixedTimestep { step: (1000000000.0 / hz as f64) as u64, max_steps, accumulator: 0 }
+  }
+
+  /// Gets the length of a step in ns.
+  pub fn step_ns(&self) -> u64 {
+    self.step
+  }
+
+  /// Adds a frame's delta to the accumulator.
+  /// # Params
+  /// * `delta` - The frame delta in ns
+  /// # Returns
+  /// The number of physics steps to run this frame
+  pub fn advance(&mut self, delta: u64) -> u32 {
+    self.accumulator += delta;
+    let steps = self.accumulator / self.step;
+    if steps > self.max_steps as u64 {
+      self.accumulator %= self.step;
+      self.max_steps
+    }
+    else {
+      self.accumulator -= steps * self.step;
+      steps as u32
+    }
+  }
+
+  /// Gets how far the accumulator is through the next step, from 0 to 1.
+  /// This is the alpha to interpolate between the last 2 physics states
+  /// with.
+  pub fn alpha(&self) -> f32 {
+    self.accumulator as f32 / self.step as f32
+  }
+}
+
+impl Default for FixedTimestep {
+  /// 60 steps per second, with at most 5 steps per frame.
+  fn default() -> FixedTimestep {
+    FixedTimestep::new(60.0, 5)
+  }
+}
@@ -1,4 +1,5 @@
 use renderer::Vertex;
+use renderer::triangulate::triangulate;
 use std::sync::mpsc;
 use std::ops::{Add, Sub, Mul};
 use cgmath::*;
@@ -15,7 +16,28 @@ impl RendererController {
   /// to get a renderer controller, look at the
   /// renderer::Renderer::get_renderer_controller() function.
   pub fn new(sender: mpsc::Sender<Vec<Vertex>>) -> RendererController {
-    RendererController { sender: sender, }
+    RendererController { sender }
+  }
+
+  /// Sends vertex data straight to the renderer. Useful for re-sending
+  /// geometry which was recorded earlier, rather than generating it again.
+  /// #Params
+  /// * `data` - The vertices to draw, 3 per triangle
+  pub fn vertices(&self, data: Vec<Vertex>) {
+    self.sender.send(data).unwrap();
+  }
+
+  /// Records the geometry drawn by a function instead of sending it to the
+  /// renderer, so it can be re-sent each frame with `vertices()` rather than
+  /// generated again.
+  /// #Params
+  /// * `draw` - Draws the geometry to the controller it's given
+  /// # Returns
+  /// The vertices drawn, 3 per triangle
+  pub fn record<F: FnOnce(&RendererController)>(draw: F) -> Vec<Vertex> {
+    let (tx, rx) = mpsc::channel();
+    draw(&RendererController::new(tx));
+    rx.try_iter().flatten().collect()
   }
 
   /// Draws a line given a start and an endpoint.
@@ -37,14 +59,14 @@ impl RendererController {
 
     // Generate the vertex data
     // tri 1
-    data.push(Vertex{ pos: [perp_l_1.x, perp_l_1.y], col: col.clone()});
-    data.push(Vertex{ pos: [perp_r_1.x, perp_r_1.y], col: col.clone()});
-    data.push(Vertex{ pos: [perp_l_2.x, perp_l_2.y], col: col.clone()});
+    data.push(Vertex{ pos: [perp_l_1.x, perp_l_1.y], col});
+    data.push(Vertex{ pos: [perp_r_1.x, perp_r_1.y], col});
+    data.push(Vertex{ pos: [perp_l_2.x, perp_l_2.y], col});
 
     // tri 2
-    data.push(Vertex{ pos: [perp_l_2.x, perp_l_2.y], col: col.clone()});
-    data.push(Vertex{ pos: [perp_r_2.x, perp_r_2.y], col: col.clone()});
-    data.push(Vertex{ pos: [perp_r_1.x, perp_r_1.y], col: col.clone()});
+    data.push(Vertex{ pos: [perp_l_2.x, perp_l_2.y], col});
+    data.push(Vertex{ pos: [perp_r_2.x, perp_r_2.y], col});
+    data.push(Vertex{ pos: [perp_r_1.x, perp_r_1.y], col});
 
     // Send the vertex data through the sender
     self.sender.send(data).unwrap();
@@ -55,20 +77,48 @@ impl RendererController {
   /// * `aabb` - The AABB box for the rectangle - X, Y, W, H
   /// * `col` - The colour of the rectangle
   pub fn rect(&self, aabb: &[f32; 4], col: &[f32; 4]) {
-    let mut data = Vec::with_capacity(6);
-
     // Generate vertex data
-    // Tri 1
-    data.push( Vertex { pos: [aabb[0], aabb[1]], col: col.clone() });
-    data.push( Vertex { pos: [aabb[0] + aabb[2], aabb[1]], col: col.clone() });
-    data.push( Vertex { pos: [aabb[0] + aabb[2], aabb[1] + aabb[3]], col: col.clone() });
+    let data = vec![
+      // Tri 1
+      Vertex { pos: [aabb[0], aabb[1]], col: *col },
+      Vertex { pos: [aabb[0] + aabb[2], aabb[1]], col: *col },
+      Vertex { pos: [aabb[0] + aabb[2], aabb[1] + aabb[3]], col: *col },
 
-    // Tri 2
-    data.push( Vertex { pos: [aabb[0], aabb[1]], col: col.clone() });
-    data.push( Vertex { pos: [aabb[0], aabb[1] + aabb[3]], col: col.clone() });
-    data.push( Vertex { pos: [aabb[0] + aabb[2], aabb[1] + aabb[3]], col: col.clone() });
+      // Tri 2
+      Vertex { pos: [aabb[0], aabb[1]], col: *col },
+      Vertex { pos: [aabb[0], aabb[1] + aabb[3]], col: *col },
+      Vertex { pos: [aabb[0] + aabb[2], aabb[1] + aabb[3]], col: *col },
+    ];
 
     // Send the data
     self.sender.send(data).unwrap();
   }
+
+  /// Draws a filled convex polygon, as a fan of triangles from the first
+  /// point. Use `simple_polygon` for concave polygons.
+  /// #Params
+  /// * `points` - The corners of the polygon, in order
+  /// * `col` - The colour of the polygon
+  pub fn polygon(&self, points: &[[f32; 2]], col: &[f32; 4]) {
+    if points.len() < 3 { return; }
+    let mut data = Vec::with_capacity((points.len() - 2) * 3);
+    for ii in 1..points.len() - 1 {
+      data.push(Vertex { pos: points[0], col: *col });
+      data.push(Vertex { pos: points[ii], col: *col });
+      data.push(Vertex { pos: points[ii + 1], col: *col });
+    }
+    self.sender.send(data).unwrap();
+  }
+
+  /// Draws a filled simple polygon, which may be concave but mustn't
+  /// intersect itself. The polygon is triangulated by ear clipping.
+  /// #Params
+  /// * `points` - The corners of the polygon, in order
+  /// * `col` - The colour of the polygon
+  pub fn simple_polygon(&self, points: &[[f32; 2]], col: &[f32; 4]) {
+    let data : Vec<Vertex> = triangulate(points).iter()
+      .flat_map(|t| t.iter().map(|&ii| Vertex { pos: points[ii], col: *col }))
+      .collect();
+    if !data.is_empty() { self.sender.send(data).unwrap(); }
+  }
 }
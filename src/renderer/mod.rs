@@ -9,8 +9,12 @@ mod system;
 /// send data to the renderer.
 mod controller;
 
+/// A module containing polygon triangulation, used to draw filled polygons.
+mod triangulate;
+
 pub use self::system::SysRenderer;
 pub use self::controller::RendererController;
+pub use self::triangulate::{is_convex, triangulate};
 
 use std::sync::mpsc;
 
@@ -52,8 +56,6 @@ impl Renderer {
   /// Create a new renderer.
   /// # Params
   /// * `display` - The glutin display (OpenGL Context)
-  /// * `system` - The SysRenderer being used by the ECS. When rendering,
-  ///              vertex data will be buffered from here.
   pub fn new(display: &GlutinFacade) -> Box<Renderer> {
     let (w, h) = display.get_window().unwrap().get_inner_size().unwrap();
     Box::new(Renderer {
@@ -117,7 +119,7 @@ impl Renderer {
 
     // Draw everything!
     target.draw(&self.vbo, 
-                &indices, 
+                indices, 
                 &self.program, 
                 &uniforms, 
                 &Default::default()).unwrap();
@@ -127,7 +129,7 @@ impl Renderer {
   /// A Sender<Vertex> for sending vertex data to the renderer. When
   /// render() is called, this data will be rendered then cleared.
   pub fn get_renderer_controller(&self) -> RendererController {
-    return RendererController::new(self.v_channel_pair.0.clone());
+    RendererController::new(self.v_channel_pair.0.clone())
   }
 }
 
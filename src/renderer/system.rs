@@ -1,8 +1,8 @@
-use renderer::{Renderer, RendererController};
+use renderer::{is_convex, Renderer, RendererController};
 
 use specs;
 use component::*;
-use state::GlobalState;
+use state::{GlobalState, Stage};
 
 /// The ECS system, which controls the buffering of vertex data into the
 /// Renderer via a system of channels.
@@ -21,16 +21,51 @@ impl SysRenderer {
   }
 }
 
-impl<'a> specs::System<GlobalState> for SysRenderer {
-  fn run(&mut self, arg: specs::RunArg, _: GlobalState) {
-    let (all_col, all_aabb) = arg.fetch(|w|  {
-      (w.read::<CompColor>(), w.read::<CompAABB>())
-    });
+impl specs::System<GlobalState> for SysRenderer {
+  fn run(&mut self, arg: specs::RunArg, g: GlobalState) {
+    if g.stage != Stage::Frame { arg.fetch(|_| ()); return; }
+    let (entities, all_col, all_aabb, all_prev, all_poly, all_shape, all_body, all_prev_angle) =
+      arg.fetch(|w|  {
+        (w.entities(), w.read::<CompColor>(), w.read::<CompAABB>(), w.read::<CompPrevAABB>(),
+         w.read::<CompPolygon>(), w.read::<CompShape>(), w.read::<CompBody>(), w.read::<CompPrevAngle>())
+      });
 
     use specs::Join;
-    for (col, aabb) in (&all_col, &all_aabb).join() {
-      self.r_controller.rect(&aabb.0, &col.0);
+    // Fill polygons first, so they're underneath everything else. Convex
+    // polygons, like terrain cells, are drawn as fans to skip ear clipping.
+    for (col, poly) in (&all_col, &all_poly).join() {
+      if is_convex(&poly.0) { self.r_controller.polygon(&poly.0, &col.0); }
+      else { self.r_controller.simple_polygon(&poly.0, &col.0); }
+    }
+    for (e, col, aabb) in (&entities, &all_col, &all_aabb).join() {
+      let lerp = |a: f32, b: f32| a + (b - a) * g.alpha;
+      match all_shape.get(e) {
+        // Entities with a shape are drawn as the shape, with its centroid and
+        // angle between the last 2 physics steps, if we know the previous
+        Some(shape) => {
+          let angle = all_body.get(e).map_or(0.0, |b| b.angle);
+          let center = shape.centroid_in(&aabb.0, angle);
+          let (center, angle) = match (all_prev.get(e), all_prev_angle.get(e)) {
+            (Some(prev), Some(prev_angle)) => {
+              let prev_center = shape.centroid_in(&prev.0, prev_angle.0);
+              ([lerp(prev_center[0], center[0]), lerp(prev_center[1], center[1])], lerp(prev_angle.0, angle))
+            }
+            _ => (center, angle),
+          };
+          self.r_controller.polygon(&shape.outline(center, angle), &col.0);
+        }
+        // Draw AABBs between the last 2 physics steps, if we know the previous
+        None => {
+          let aabb = match all_prev.get(e) {
+            Some(prev) => {
+              let lerp = |ii: usize| lerp(prev.0[ii], aabb.0[ii]);
+              [lerp(0), lerp(1), lerp(2), lerp(3)]
+            }
+            None => aabb.0,
+          };
+          self.r_controller.rect(&aabb, &col.0);
+        }
+      }
     }
   }
 }
-
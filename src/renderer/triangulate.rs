@@ -0,0 +1,84 @@
+/// Checks whether a simple polygon (one which doesn't intersect itself) is
+/// convex, in either winding order. Straight corners are allowed. Convex
+/// polygons can be drawn as a fan of triangles, without ear clipping.
+pub fn is_convex(poly: &[[f32; 2]]) -> bool {
+  let n = poly.len();
+  let (mut left, mut right) = (false, false);
+  for ii in 0..n {
+    let (a, b, c) = (poly[ii], poly[(ii + 1) % n], poly[(ii + 2) % n]);
+    let cross = (b[0] - a[0])*(c[1] - b[1]) - (b[1] - a[1])*(c[0] - b[0]);
+    left |= cross > 0.0;
+    right |= cross < 0.0;
+  }
+  !(left && right)
+}
+
+/// Triangulates a simple polygon (one which doesn't intersect itself) by ear
+/// clipping, in O(n^2) time. Works for concave polygons, in either winding
+/// order.
+/// # Params
+/// * `poly` - The points of the polygon, in order
+/// # Returns
+/// Triangles as indices into `poly`, each counter-clockwise (positive signed
+/// area). There are `poly.len() - 2` triangles, or none if the polygon has
+/// fewer than 3 points.
+pub fn triangulate(poly: &[[f32; 2]]) -> Vec<[usize; 3]> {
+  let n = poly.len();
+  if n < 3 { return Vec::new(); }
+  let cross = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| (b[0] - a[0])*(c[1] - a[1]) - (b[1] - a[1])*(c[0] - a[0]);
+
+  // Work counter-clockwise, so ears are the corners turning left
+  let area2 = (0..n).fold(0.0, |s, ii| {
+    let (a, b) = (poly[ii], poly[(ii + 1) % n]);
+    s + a[0]*b[1] - b[0]*a[1]
+  });
+  let mut remaining : Vec<usize> = if area2 >= 0.0 { (0..n).collect() } else { (0..n).rev().collect() };
+
+  let mut tris = Vec::with_capacity(n - 2);
+  let mut ii = 0;
+  // The number of corners checked since the last ear was clipped
+  let mut since_ear = 0;
+  while remaining.len() > 3 {
+    let m = remaining.len();
+    ii %= m;
+    let (a, b, c) = (remaining[(ii + m - 1) % m], remaining[ii], remaining[(ii + 1) % m]);
+    let (pa, pb, pc) = (poly[a], poly[b], poly[c]);
+    // A corner is an ear if it's convex and no other corner is inside it
+    let is_ear = cross(pa, pb, pc) > 0.0 && !remaining.iter().any(|&v| {
+      let p = poly[v];
+      v != a && v != b && v != c && cross(pa, pb, p) >= 0.0 && cross(pb, pc, p) >= 0.0 && cross(pc, pa, p) >= 0.0
+    });
+    // If no corner is an ear, the polygon is degenerate (e.g. has collinear
+    // or repeated points), so clip anyway rather than looping forever
+    if is_ear || since_ear >= m {
+      tris.push([a, b, c]);
+      remaining.remove(ii);
+      since_ear = 0;
+    }
+    else {
+      ii += 1;
+      since_ear += 1;
+    }
+  }
+  tris.push([remaining[0], remaining[1], remaining[2]]);
+  tris
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn convex() {
+    let square = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+    assert!(is_convex(&square));
+    let mut clockwise = square;
+    clockwise.reverse();
+    assert!(is_convex(&clockwise));
+    // A straight corner in the middle of a side
+    assert!(is_convex(&[[0.0, 0.0], [0.5, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]));
+    let arrow = [[0.0, 0.0], [1.0, 0.5], [0.0, 1.0], [0.3, 0.5]];
+    assert!(!is_convex(&arrow));
+    assert_eq!(triangulate(&arrow).len(), 2);
+  }
+}
@@ -1,16 +1,35 @@
+/// What a dispatch of the ECS is for. Each frame, the ECS is dispatched for
+/// zero or more physics steps, then once for the frame itself. Systems should
+/// do nothing in a stage they're not for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+  /// A fixed length physics step
+  Physics,
+  /// A frame to be rendered
+  Frame,
+}
+
 #[derive(Clone)]
 pub struct GlobalState { 
   /// Previous time in ns - unspecified epoch
   pub prev_time: u64, 
-  /// Frame delta in ns
+  /// Frame delta in ns. In the physics stage, this is the length of a
+  /// physics step instead.
   pub delta: u64, 
-
+  /// The seed the world was generated from. Generators given the same seed
+  /// produce the same output, so this is enough to reproduce a world.
+  pub seed: u64,
+  /// The stage this dispatch is for
+  pub stage: Stage,
+  /// How far the frame is between the last 2 physics steps, from 0 (the
+  /// step before last) to 1 (the last step). Used to interpolate positions
+  /// for rendering.
+  pub alpha: f32,
 }
 
 impl GlobalState {
   /// Get the current frame delta in seconds.
   pub fn get_delta_in_s(&self) -> f32 {
-    return self.delta as f32 / 1000000000.0f32;
+    self.delta as f32 / 1000000000.0f32
   }
 }
-
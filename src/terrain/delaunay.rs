@@ -0,0 +1,63 @@
+//! The Delaunay triangulation of a set of sites, computed as the dual of their
+//! Voronoi diagram.
+
+use renderer::RendererController;
+use terrain::voronoi::{Edge, VoronoiDiagram};
+use cgmath::Vector2;
+
+/// The Delaunay triangulation of a set of sites. Each triangle corresponds to
+/// a vertex of the Voronoi diagram, and each pair of neighbouring sites to a
+/// Voronoi edge.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delaunay {
+  /// The triangles, as indices into the list of sites. Triangles are
+  /// counter-clockwise (positive signed area), the same as Voronoi cells.
+  pub triangles: Vec<[usize; 3]>,
+  /// For each site, the sorted indices of the sites whose cells share an edge
+  /// with its cell.
+  ///
+  /// When 4 or more sites lie on a circle, the triangulation has to choose a
+  /// diagonal, but the cells at either end of it only meet at a point. Those
+  /// sites aren't neighbours.
+  pub neighbours: Vec<Vec<usize>>,
+}
+
+impl Delaunay {
+  /// Creates a triangulation from its triangles and the Voronoi edges between
+  /// the sites.
+  /// # Params
+  /// * `num_sites` - The number of sites
+  /// * `triangles` - The triangles, counter-clockwise
+  /// * `edges` - The (unclipped) Voronoi edges, used to find neighbours
+  pub fn new(num_sites: usize, triangles: Vec<[usize; 3]>, edges: &[Edge]) -> Delaunay {
+    let mut neighbours = vec![Vec::new(); num_sites];
+    for e in edges {
+      neighbours[e.sites[0]].push(e.sites[1]);
+      neighbours[e.sites[1]].push(e.sites[0]);
+    }
+    for n in &mut neighbours {
+      n.sort();
+      n.dedup();
+    }
+    Delaunay { triangles, neighbours }
+  }
+
+  /// Checks whether the cells of 2 sites share an edge.
+  pub fn are_neighbours(&self, a: usize, b: usize) -> bool {
+    self.neighbours[a].binary_search(&b).is_ok()
+  }
+}
+
+/// Draws the Delaunay triangulation of a diagram for debugging, as magenta
+/// triangle outlines.
+/// # Params
+/// * `d` - The diagram to draw the triangulation of
+/// * `r` - The renderer controller to send the geometry to
+pub fn draw_delaunay(d: &VoronoiDiagram, r: &RendererController) {
+  for t in &d.delaunay.triangles {
+    for ii in 0..3 {
+      let (p0, p1) = (d.sites[t[ii]], d.sites[t[(ii + 1) % 3]]);
+      r.line(Vector2::new(p0[0], p0[1]), Vector2::new(p1[0], p1[1]), 1.0, [1.0, 0.0, 1.0, 1.0]);
+    }
+  }
+}
@@ -0,0 +1,87 @@
+//! A world resource owning a set of sites and their Voronoi diagram, so the
+//! diagram is only rebuilt when the sites change.
+
+use renderer::{RendererController, Vertex};
+use terrain::voronoi::{self, VoronoiDiagram};
+
+/// Owns a list of sites and the diagram computed from them. Changing the sites
+/// marks the diagram dirty, and it's rebuilt on the next `update()`.
+///
+/// The debug geometry of the diagram (see `voronoi::draw_voronoi()`) is
+/// recorded when the diagram is built, and re-sent by `draw()` each frame. Add
+/// this to the world as a resource, along with `terrain::SysTerrain` to keep
+/// it up to date and draw it.
+#[derive(Clone, Debug)]
+pub struct TerrainDiagram {
+  sites: Vec<[f32; 2]>,
+  /// The rectangle to clip the diagram to, if any - X, Y, W, H
+  bounds: Option<[f32; 4]>,
+  diagram: VoronoiDiagram,
+  /// Whether the sites have changed since the diagram was built
+  dirty: bool,
+  /// The debug geometry of the diagram, recorded when it was built
+  geometry: Vec<Vertex>,
+}
+
+impl TerrainDiagram {
+  /// Creates a terrain diagram, building the diagram of the given sites.
+  /// # Params
+  /// * `sites` - The sites of the diagram
+  /// * `bounds` - The rectangle to clip the diagram to - X, Y, W, H - or
+  ///   `None` for an unbounded diagram
+  pub fn new(sites: Vec<[f32; 2]>, bounds: Option<[f32; 4]>) -> TerrainDiagram {
+    let mut t = TerrainDiagram {
+      sites, bounds, diagram: voronoi::voronoi(&[]), dirty: true, geometry: Vec::new(),
+    };
+    t.update();
+    t
+  }
+
+  /// Gets the sites.
+  pub fn sites(&self) -> &[[f32; 2]] {
+    &self.sites
+  }
+
+  /// Gets the sites to change them, marking the diagram dirty.
+  pub fn sites_mut(&mut self) -> &mut Vec<[f32; 2]> {
+    self.dirty = true;
+    &mut self.sites
+  }
+
+  /// Replaces the sites, marking the diagram dirty.
+  pub fn set_sites(&mut self, sites: Vec<[f32; 2]>) {
+    self.dirty = true;
+    self.sites = sites;
+  }
+
+  /// Checks whether the sites have changed since the diagram was built.
+  pub fn is_dirty(&self) -> bool {
+    self.dirty
+  }
+
+  /// Gets the diagram, as of the last `update()`.
+  pub fn diagram(&self) -> &VoronoiDiagram {
+    &self.diagram
+  }
+
+  /// Rebuilds the diagram and its geometry, if the sites have changed.
+  /// # Returns
+  /// True if the diagram was rebuilt.
+  pub fn update(&mut self) -> bool {
+    if !self.dirty { return false; }
+    self.diagram = match self.bounds {
+      Some(b) => voronoi::voronoi_bounded(&self.sites, &b),
+      None => voronoi::voronoi(&self.sites),
+    };
+
+    let diagram = &self.diagram;
+    self.geometry = RendererController::record(|r| voronoi::draw_voronoi(diagram, r));
+    self.dirty = false;
+    true
+  }
+
+  /// Sends the diagram's cached debug geometry to the renderer.
+  pub fn draw(&self, r: &RendererController) {
+    if !self.geometry.is_empty() { r.vertices(self.geometry.clone()); }
+  }
+}
@@ -0,0 +1,561 @@
+//! Exporting terrain to files, so maps can be looked at without running the
+//! game - e.g. attached to bug reports.
+//!
+//! SVG export draws cell polygons, edges and sites, with cells coloured by an
+//! attribute. JSON export writes a `VoronoiDiagram` in full, optionally with
+//! the rest of a `TerrainMap` and its roads, and `diagram_from_json()` and
+//! `map_from_json()` load them back exactly - floats are written with the
+//! fewest digits which read back to the same value.
+
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use terrain::delaunay::Delaunay;
+use terrain::map::{Biome, CellKind, TerrainCell, TerrainMap};
+use terrain::mesh::Mesh;
+use terrain::river::{Drainage, River};
+use terrain::road::{Road, RoadNetwork};
+use terrain::voronoi::{Cell, Edge, VoronoiDiagram};
+
+/// The attribute used to colour cells when exporting a map to SVG.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorBy {
+  Biome,
+  /// Black at elevation 0 to white at elevation 1
+  Elevation,
+  /// White when dry to blue when wet
+  Moisture,
+}
+
+/// Formats a colour as an SVG hex colour. Alpha is ignored.
+fn svg_color(col: [f32; 4]) -> String {
+  let c = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+  format!("#{:02x}{:02x}{:02x}", c(col[0]), c(col[1]), c(col[2]))
+}
+
+/// Writes a bounded Voronoi diagram as an SVG image. Cells are filled, finite
+/// edges are drawn as black lines, and sites as red dots.
+/// # Params
+/// * `d` - The diagram to write. Unbounded diagrams have no cell polygons, so
+///   only their edges and sites are drawn.
+/// * `fill` - Gets the fill colour of a cell
+pub fn diagram_to_svg<F: Fn(usize) -> [f32; 4]>(d: &VoronoiDiagram, fill: F) -> String {
+  let b = d.bounds.unwrap_or_else(|| {
+    let (mut min, mut max) = ([f32::MAX; 2], [f32::MIN; 2]);
+    for p in d.sites.iter().chain(&d.vertices).filter(|p| p[0].is_finite() && p[1].is_finite()) {
+      min = [min[0].min(p[0]), min[1].min(p[1])];
+      max = [max[0].max(p[0]), max[1].max(p[1])];
+    }
+    if min[0] > max[0] { [0.0, 0.0, 1.0, 1.0] } else { [min[0], min[1], max[0] - min[0], max[1] - min[1]] }
+  });
+
+  let mut svg = String::new();
+  writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{:?} {:?} {:?} {:?}" width="{:?}" height="{:?}">"#,
+           b[0], b[1], b[2], b[3], b[2], b[3]).unwrap();
+
+  svg.push_str("<g id=\"cells\" stroke=\"none\">\n");
+  for (ii, c) in d.cells.iter().enumerate() {
+    if c.vertices.is_empty() { continue; }
+    let points : Vec<String> = c.vertices.iter().map(|&v| format!("{:?},{:?}", d.vertices[v][0], d.vertices[v][1])).collect();
+    writeln!(svg, r#"<polygon points="{}" fill="{}"/>"#, points.join(" "), svg_color(fill(ii))).unwrap();
+  }
+  svg.push_str("</g>\n<g id=\"edges\" stroke=\"#000000\" stroke-width=\"0.5\">\n");
+  for e in &d.edges {
+    if let [Some(v0), Some(v1)] = e.vertices {
+      let (p0, p1) = (d.vertices[v0], d.vertices[v1]);
+      writeln!(svg, r#"<line x1="{:?}" y1="{:?}" x2="{:?}" y2="{:?}"/>"#, p0[0], p0[1], p1[0], p1[1]).unwrap();
+    }
+  }
+  svg.push_str("</g>\n<g id=\"sites\" fill=\"#ff0000\">\n");
+  for p in d.sites.iter().filter(|p| p[0].is_finite() && p[1].is_finite()) {
+    writeln!(svg, r#"<circle cx="{:?}" cy="{:?}" r="1.5"/>"#, p[0], p[1]).unwrap();
+  }
+  svg.push_str("</g>\n</svg>\n");
+  svg
+}
+
+/// Writes a terrain map as an SVG image, with cells coloured by an attribute.
+pub fn map_to_svg(map: &TerrainMap, color_by: ColorBy) -> String {
+  diagram_to_svg(&map.diagram, |ii| {
+    let c = &map.cells[ii];
+    match color_by {
+      ColorBy::Biome => c.biome.color(),
+      ColorBy::Elevation => [c.elevation, c.elevation, c.elevation, 1.0],
+      ColorBy::Moisture => [1.0 - c.moisture, 1.0 - c.moisture * 0.5, 1.0, 1.0],
+    }
+  })
+}
+
+/// Formats a float for JSON. Non-finite floats are written as `null`.
+fn num(x: f32) -> String {
+  if x.is_finite() { format!("{:?}", x) } else { "null".to_string() }
+}
+
+fn point(p: &[f32; 2]) -> String {
+  format!("[{},{}]", num(p[0]), num(p[1]))
+}
+
+fn opt(v: Option<usize>) -> String {
+  v.map_or("null".to_string(), |v| v.to_string())
+}
+
+fn list<T: ToString>(items: &[T]) -> String {
+  format!("[{}]", items.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(","))
+}
+
+fn nums(items: &[f32]) -> String {
+  format!("[{}]", items.iter().map(|&x| num(x)).collect::<Vec<_>>().join(","))
+}
+
+/// Writes the fields of a diagram, without the object's braces or a trailing
+/// comma.
+fn diagram_fields(d: &VoronoiDiagram) -> String {
+  let join = |items: Vec<String>| items.join(",\n    ");
+
+  let mut json = String::new();
+  writeln!(json, "  \"sites\": [\n    {}\n  ],", join(d.sites.iter().map(point).collect())).unwrap();
+  writeln!(json, "  \"vertices\": [\n    {}\n  ],", join(d.vertices.iter().map(point).collect())).unwrap();
+  writeln!(json, "  \"edges\": [\n    {}\n  ],", join(d.edges.iter().map(|e| {
+    format!("{{\"sites\":{},\"vertices\":[{},{}]}}", list(&e.sites), opt(e.vertices[0]), opt(e.vertices[1]))
+  }).collect())).unwrap();
+  writeln!(json, "  \"cells\": [\n    {}\n  ],", join(d.cells.iter().map(|c| {
+    format!("{{\"site\":{},\"edges\":{},\"vertices\":{}}}", c.site, list(&c.edges), list(&c.vertices))
+  }).collect())).unwrap();
+  match d.bounds {
+    Some(ref b) => writeln!(json, "  \"bounds\": {},", nums(b)).unwrap(),
+    None => json.push_str("  \"bounds\": null,\n"),
+  }
+  match d.weights {
+    Some(ref w) => writeln!(json, "  \"weights\": {},", nums(w)).unwrap(),
+    None => json.push_str("  \"weights\": null,\n"),
+  }
+  write!(json, "  \"delaunay\": {{\n    \"triangles\": [{}],\n    \"neighbours\": [{}]\n  }}",
+         d.delaunay.triangles.iter().map(|t| list(t)).collect::<Vec<_>>().join(","),
+         d.delaunay.neighbours.iter().map(|n| list(n)).collect::<Vec<_>>().join(",")).unwrap();
+  json
+}
+
+/// Writes a Voronoi diagram as JSON. Non-finite coordinates (e.g. removed
+/// sites) are written as `null`, and read back as NaN.
+pub fn diagram_to_json(d: &VoronoiDiagram) -> String {
+  format!("{{\n{}\n}}\n", diagram_fields(d))
+}
+
+/// Writes a terrain map and its roads as JSON. This has the same fields as
+/// `diagram_to_json()`, so `diagram_from_json()` can load just the diagram,
+/// plus the attributes of each region, the drainage and rivers, and the
+/// roads.
+pub fn map_to_json(map: &TerrainMap, roads: &RoadNetwork) -> String {
+  let join = |items: Vec<String>| items.join(",\n    ");
+  let d = &map.drainage;
+
+  let mut json = format!("{{\n{},\n", diagram_fields(&map.diagram));
+  writeln!(json, "  \"sea_level\": {},", num(map.sea_level)).unwrap();
+  writeln!(json, "  \"regions\": [\n    {}\n  ],", join(map.cells.iter().map(|c| {
+    format!("{{\"elevation\":{},\"moisture\":{},\"kind\":\"{:?}\",\"biome\":\"{:?}\"}}",
+            num(c.elevation), num(c.moisture), c.kind, c.biome)
+  }).collect())).unwrap();
+  writeln!(json, "  \"drainage\": {{\n    \"elevation\": {},\n    \"water_level\": {},\n    \"downstream\": [{}],\n    \
+                  \"flow\": {},\n    \"lake\": {},\n    \"river\": {},\n    \"rivers\": [{}]\n  }},",
+           nums(&d.elevation), nums(&d.water_level), d.downstream.iter().map(|&v| opt(v)).collect::<Vec<_>>().join(","),
+           nums(&d.flow), list(&d.lake), list(&d.river),
+           d.rivers.iter().map(|r| list(&r.corners)).collect::<Vec<_>>().join(",")).unwrap();
+  writeln!(json, "  \"roads\": {{\n    \"settlements\": {},\n    \"roads\": [{}],\n    \"segments\": [{}]\n  }}",
+           list(&roads.settlements),
+           roads.roads.iter().map(|r| {
+             format!("{{\"settlements\":{},\"corners\":{},\"cost\":{}}}", list(&r.settlements), list(&r.corners), num(r.cost))
+           }).collect::<Vec<_>>().join(","),
+           roads.segments.iter().map(|s| list(s)).collect::<Vec<_>>().join(",")).unwrap();
+  json.push_str("}\n");
+  json
+}
+
+/// An error loading JSON - either invalid JSON, or JSON which isn't a valid
+/// diagram or map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError(pub String);
+
+impl fmt::Display for JsonError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Invalid terrain JSON: {}", self.0)
+  }
+}
+
+impl Error for JsonError {}
+
+/// Parses a whole JSON document.
+fn parse(json: &str) -> Result<Json, JsonError> {
+  let mut parser = Parser { s: json.as_bytes(), pos: 0 };
+  let root = parser.value()?;
+  parser.skip_whitespace();
+  if parser.pos != parser.s.len() { return Err(parser.error("trailing characters")); }
+  Ok(root)
+}
+
+fn points(v: &Json) -> Result<Vec<[f32; 2]>, JsonError> {
+  v.array()?.iter().map(|p| {
+    let p = p.array()?;
+    if p.len() != 2 { return Err(JsonError("points must have 2 coordinates".to_string())); }
+    Ok([p[0].f32()?, p[1].f32()?])
+  }).collect()
+}
+
+fn indices(v: &Json) -> Result<Vec<usize>, JsonError> {
+  v.array()?.iter().map(Json::usize).collect()
+}
+
+fn floats(v: &Json) -> Result<Vec<f32>, JsonError> {
+  v.array()?.iter().map(Json::f32).collect()
+}
+
+fn bools(v: &Json) -> Result<Vec<bool>, JsonError> {
+  v.array()?.iter().map(Json::bool).collect()
+}
+
+/// Reads an index pair, e.g. the 2 ends of a road.
+fn pair(v: &Json) -> Result<[usize; 2], JsonError> {
+  let p = indices(v)?;
+  if p.len() != 2 { return Err(JsonError("expected 2 indices".to_string())); }
+  Ok([p[0], p[1]])
+}
+
+/// Every `CellKind` and `Biome`, so they can be read back from their names.
+const KINDS : [CellKind; 4] = [CellKind::Ocean, CellKind::Lake, CellKind::Coast, CellKind::Land];
+const BIOMES : [Biome; 16] = [
+  Biome::Ocean, Biome::Lake, Biome::Beach, Biome::Snow, Biome::Tundra, Biome::Bare, Biome::Scorched,
+  Biome::Taiga, Biome::Shrubland, Biome::TemperateDesert, Biome::TemperateRainForest,
+  Biome::TemperateDeciduousForest, Biome::Grassland, Biome::TropicalRainForest,
+  Biome::TropicalSeasonalForest, Biome::SubtropicalDesert,
+];
+
+/// Reads an enum written by name, given all its values.
+fn named<T: Copy + fmt::Debug>(all: &[T], v: &Json) -> Result<T, JsonError> {
+  let name = v.string()?;
+  all.iter().cloned().find(|x| format!("{:?}", x) == name)
+    .ok_or_else(|| JsonError(format!("unknown name \"{}\"", name)))
+}
+
+/// Loads a Voronoi diagram written by `diagram_to_json()` or `map_to_json()`.
+pub fn diagram_from_json(json: &str) -> Result<VoronoiDiagram, JsonError> {
+  diagram_from(&parse(json)?)
+}
+
+fn diagram_from(root: &Json) -> Result<VoronoiDiagram, JsonError> {
+  let sites = points(root.get("sites")?)?;
+  let vertices = points(root.get("vertices")?)?;
+  let edges = root.get("edges")?.array()?.iter().map(|e| {
+    let v = e.get("vertices")?.array()?;
+    if v.len() != 2 { return Err(JsonError("edges must have 2 sites and 2 vertices".to_string())); }
+    let end = |j: &Json| if *j == Json::Null { Ok(None) } else { j.usize().map(Some) };
+    Ok(Edge { sites: pair(e.get("sites")?)?, vertices: [end(&v[0])?, end(&v[1])?] })
+  }).collect::<Result<Vec<Edge>, JsonError>>()?;
+  let cells = root.get("cells")?.array()?.iter().map(|c| {
+    Ok(Cell { site: c.get("site")?.usize()?, edges: indices(c.get("edges")?)?, vertices: indices(c.get("vertices")?)? })
+  }).collect::<Result<Vec<Cell>, JsonError>>()?;
+  let bounds = match *root.get("bounds")? {
+    Json::Null => None,
+    ref b => {
+      let b = floats(b)?;
+      if b.len() != 4 { return Err(JsonError("bounds must have 4 numbers".to_string())); }
+      Some([b[0], b[1], b[2], b[3]])
+    }
+  };
+  // Files written before weights were added have no weights field
+  let weights = match root.get("weights") {
+    Ok(&Json::Null) | Err(_) => None,
+    Ok(w) => Some(floats(w)?),
+  };
+  let delaunay = root.get("delaunay")?;
+  let triangles = delaunay.get("triangles")?.array()?.iter().map(|t| {
+    let t = indices(t)?;
+    if t.len() != 3 { return Err(JsonError("triangles must have 3 sites".to_string())); }
+    Ok([t[0], t[1], t[2]])
+  }).collect::<Result<Vec<[usize; 3]>, JsonError>>()?;
+  let neighbours = delaunay.get("neighbours")?.array()?.iter().map(indices).collect::<Result<Vec<_>, _>>()?;
+
+  // Check indices, so a bad file can't cause a panic later
+  let (ns, nv, ne) = (sites.len(), vertices.len(), edges.len());
+  let valid = edges.iter().all(|e| e.sites.iter().all(|&s| s < ns) && e.vertices.iter().flatten().all(|&v| v < nv)) &&
+    cells.len() == ns && weights.as_ref().map_or(true, |w| w.len() == ns) &&
+    cells.iter().all(|c| c.site < ns && c.edges.iter().all(|&e| e < ne) && c.vertices.iter().all(|&v| v < nv)) &&
+    triangles.iter().all(|t| t.iter().all(|&s| s < ns)) &&
+    neighbours.len() == ns && neighbours.iter().all(|n| n.iter().all(|&s| s < ns));
+  if !valid { return Err(JsonError("index out of range".to_string())); }
+
+  Ok(VoronoiDiagram { sites, vertices, edges, cells, bounds, weights, delaunay: Delaunay { triangles, neighbours } })
+}
+
+/// Loads a terrain map and its roads written by `map_to_json()`. The map's
+/// mesh is rebuilt from its diagram.
+pub fn map_from_json(json: &str) -> Result<(TerrainMap, RoadNetwork), JsonError> {
+  let root = parse(json)?;
+  let diagram = diagram_from(&root)?;
+  let mesh = Mesh::from_voronoi(&diagram);
+
+  let cells = root.get("regions")?.array()?.iter().map(|c| {
+    Ok(TerrainCell {
+      elevation: c.get("elevation")?.f32()?,
+      moisture: c.get("moisture")?.f32()?,
+      kind: named(&KINDS, c.get("kind")?)?,
+      biome: named(&BIOMES, c.get("biome")?)?,
+    })
+  }).collect::<Result<Vec<TerrainCell>, JsonError>>()?;
+
+  let d = root.get("drainage")?;
+  let downstream = d.get("downstream")?.array()?.iter()
+    .map(|j| if *j == Json::Null { Ok(None) } else { j.usize().map(Some) })
+    .collect::<Result<Vec<Option<usize>>, JsonError>>()?;
+  let rivers = d.get("rivers")?.array()?.iter().map(|r| Ok(River { corners: indices(r)? }))
+    .collect::<Result<Vec<River>, JsonError>>()?;
+  let drainage = Drainage {
+    elevation: floats(d.get("elevation")?)?,
+    water_level: floats(d.get("water_level")?)?,
+    downstream,
+    flow: floats(d.get("flow")?)?,
+    lake: bools(d.get("lake")?)?,
+    river: bools(d.get("river")?)?,
+    rivers,
+  };
+
+  let r = root.get("roads")?;
+  let roads = RoadNetwork {
+    settlements: indices(r.get("settlements")?)?,
+    roads: r.get("roads")?.array()?.iter().map(|road| {
+      Ok(Road { settlements: pair(road.get("settlements")?)?, corners: indices(road.get("corners")?)?, cost: road.get("cost")?.f32()? })
+    }).collect::<Result<Vec<Road>, JsonError>>()?,
+    segments: r.get("segments")?.array()?.iter().map(pair).collect::<Result<Vec<[usize; 2]>, JsonError>>()?,
+  };
+
+  // Check lengths and indices, as for diagrams
+  let (ns, nv) = (diagram.sites.len(), mesh.vertices.len());
+  let corner = |&c: &usize| c < nv;
+  let valid = cells.len() == ns &&
+    [drainage.elevation.len(), drainage.water_level.len(), drainage.downstream.len(), drainage.flow.len(),
+     drainage.lake.len(), drainage.river.len()].iter().all(|&len| len == nv) &&
+    drainage.downstream.iter().flatten().all(corner) && drainage.rivers.iter().all(|r| r.corners.iter().all(corner)) &&
+    roads.settlements.iter().all(|&s| s < ns) &&
+    roads.roads.iter().all(|r| r.settlements.iter().all(|&s| s < ns) && r.corners.iter().all(corner)) &&
+    roads.segments.iter().all(|s| s.iter().all(corner));
+  if !valid { return Err(JsonError("index out of range".to_string())); }
+
+  let sea_level = root.get("sea_level")?.f32()?;
+  Ok((TerrainMap { diagram, mesh, cells, sea_level, drainage }, roads))
+}
+
+/// A parsed JSON value. Numbers are kept as text, so they can be parsed
+/// straight to the type they're read as, without rounding through `f64`.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+  Null,
+  Bool(bool),
+  Number(String),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  fn get(&self, key: &str) -> Result<&Json, JsonError> {
+    match *self {
+      Json::Object(ref fields) => fields.iter().find(|f| f.0 == key).map(|f| &f.1)
+        .ok_or_else(|| JsonError(format!("missing field \"{}\"", key))),
+      _ => Err(JsonError(format!("expected an object with field \"{}\"", key))),
+    }
+  }
+
+  fn array(&self) -> Result<&[Json], JsonError> {
+    match *self {
+      Json::Array(ref items) => Ok(items),
+      _ => Err(JsonError("expected an array".to_string())),
+    }
+  }
+
+  fn number<T: FromStr>(&self) -> Result<T, JsonError> {
+    match *self {
+      Json::Number(ref n) => n.parse().map_err(|_| JsonError(format!("invalid number {}", n))),
+      _ => Err(JsonError("expected a number".to_string())),
+    }
+  }
+
+  /// Reads a float. `null` is read as NaN.
+  fn f32(&self) -> Result<f32, JsonError> {
+    if *self == Json::Null { Ok(f32::NAN) } else { self.number() }
+  }
+
+  fn usize(&self) -> Result<usize, JsonError> {
+    self.number()
+  }
+
+  fn bool(&self) -> Result<bool, JsonError> {
+    match *self {
+      Json::Bool(b) => Ok(b),
+      _ => Err(JsonError("expected true or false".to_string())),
+    }
+  }
+
+  fn string(&self) -> Result<&str, JsonError> {
+    match *self {
+      Json::String(ref s) => Ok(s),
+      _ => Err(JsonError("expected a string".to_string())),
+    }
+  }
+}
+
+/// A recursive descent JSON parser.
+struct Parser<'a> {
+  s: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn error(&self, message: &str) -> JsonError {
+    JsonError(format!("{} at byte {}", message, self.pos))
+  }
+
+  fn skip_whitespace(&mut self) {
+    while self.pos < self.s.len() && (self.s[self.pos] as char).is_ascii_whitespace() { self.pos += 1; }
+  }
+
+  fn peek(&mut self) -> Option<u8> {
+    self.skip_whitespace();
+    self.s.get(self.pos).cloned()
+  }
+
+  fn expect(&mut self, c: u8) -> Result<(), JsonError> {
+    if self.peek() != Some(c) { return Err(self.error(&format!("expected '{}'", c as char))); }
+    self.pos += 1;
+    Ok(())
+  }
+
+  /// Parses the rest of a comma separated list, after its opening bracket.
+  fn list<T, F: FnMut(&mut Parser<'a>) -> Result<T, JsonError>>(&mut self, close: u8, mut item: F) -> Result<Vec<T>, JsonError> {
+    let mut items = Vec::new();
+    if self.peek() == Some(close) {
+      self.pos += 1;
+      return Ok(items);
+    }
+    loop {
+      items.push(item(self)?);
+      match self.peek() {
+        Some(b',') => self.pos += 1,
+        Some(c) if c == close => { self.pos += 1; return Ok(items); }
+        _ => return Err(self.error(&format!("expected ',' or '{}'", close as char))),
+      }
+    }
+  }
+
+  fn value(&mut self) -> Result<Json, JsonError> {
+    match self.peek() {
+      Some(b'{') => {
+        self.pos += 1;
+        self.list(b'}', |p| {
+          let key = p.string()?;
+          p.expect(b':')?;
+          Ok((key, p.value()?))
+        }).map(Json::Object)
+      }
+      Some(b'[') => {
+        self.pos += 1;
+        self.list(b']', |p| p.value()).map(Json::Array)
+      }
+      Some(b'"') => self.string().map(Json::String),
+      Some(b'-') | Some(b'0'..=b'9') => {
+        let start = self.pos;
+        while self.pos < self.s.len() && b"+-.eE0123456789".contains(&self.s[self.pos]) { self.pos += 1; }
+        Ok(Json::Number(String::from_utf8_lossy(&self.s[start..self.pos]).into_owned()))
+      }
+      _ => {
+        for &(word, ref value) in &[("null", Json::Null), ("true", Json::Bool(true)), ("false", Json::Bool(false))] {
+          if self.s[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            return Ok(value.clone());
+          }
+        }
+        Err(self.error("expected a value"))
+      }
+    }
+  }
+
+  fn string(&mut self) -> Result<String, JsonError> {
+    self.expect(b'"')?;
+    let mut out = String::new();
+    loop {
+      // Copy everything up to the next quote or escape
+      let start = self.pos;
+      while self.pos < self.s.len() && self.s[self.pos] != b'"' && self.s[self.pos] != b'\\' { self.pos += 1; }
+      out.push_str(&String::from_utf8_lossy(&self.s[start..self.pos]));
+      match self.s.get(self.pos) {
+        Some(b'"') => { self.pos += 1; return Ok(out); }
+        Some(b'\\') => {
+          let c = match self.s.get(self.pos + 1) {
+            Some(b'"') => '"', Some(b'\\') => '\\', Some(b'/') => '/',
+            Some(b'b') => '\u{8}', Some(b'f') => '\u{c}', Some(b'n') => '\n', Some(b'r') => '\r', Some(b't') => '\t',
+            Some(b'u') => {
+              let hex = self.s.get(self.pos + 2..self.pos + 6).and_then(|h| ::std::str::from_utf8(h).ok());
+              let code = hex.and_then(|h| u32::from_str_radix(h, 16).ok()).ok_or_else(|| self.error("invalid escape"))?;
+              self.pos += 4;
+              // Surrogate pairs aren't combined, as diagrams never contain them
+              ::std::char::from_u32(code).unwrap_or('\u{fffd}')
+            }
+            _ => return Err(self.error("invalid escape")),
+          };
+          out.push(c);
+          self.pos += 2;
+        }
+        _ => return Err(self.error("unterminated string")),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use terrain::map::MapConfig;
+  use terrain::road::pick_settlements;
+  use terrain::voronoi::{voronoi, voronoi_weighted};
+
+  const BOUNDS : [f32; 4] = [0.0, 0.0, 800.0, 600.0];
+
+  #[test]
+  fn diagram_round_trip() {
+    let sites = [[120.5, 80.25], [700.0, 300.0], [400.1, 550.9], [33.3, 450.0], [410.0, 290.0]];
+    let unbounded = voronoi(&sites);
+    assert_eq!(diagram_from_json(&diagram_to_json(&unbounded)), Ok(unbounded));
+    let weighted = voronoi_weighted(&sites, &[0.0, 2500.0, 0.0, 100.0, 0.0], &BOUNDS);
+    assert_eq!(diagram_from_json(&diagram_to_json(&weighted)), Ok(weighted));
+  }
+
+  #[test]
+  fn map_round_trip() {
+    let map = TerrainMap::generate(&MapConfig { num_regions: 200, .. Default::default() }, 7);
+    let roads = RoadNetwork::generate(&map, &pick_settlements(&map, 5, 7), &Default::default());
+    assert!(!roads.roads.is_empty() && !map.drainage.rivers.is_empty());
+    let json = map_to_json(&map, &roads);
+    assert_eq!(map_from_json(&json), Ok((map.clone(), roads)));
+    // The diagram can also be loaded on its own
+    assert_eq!(diagram_from_json(&json), Ok(map.diagram));
+  }
+
+  #[test]
+  fn map_svg() {
+    let map = TerrainMap::generate(&MapConfig { num_regions: 100, .. Default::default() }, 7);
+    let svg = map_to_svg(&map, ColorBy::Biome);
+    assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"));
+    let cells = map.diagram.cells.iter().filter(|c| !c.vertices.is_empty()).count();
+    assert_eq!(svg.matches("<polygon ").count(), cells);
+    assert_eq!(svg.matches("<circle ").count(), map.diagram.sites.len());
+    // Each cell is filled with its biome's colour
+    let first = map.diagram.cells.iter().position(|c| !c.vertices.is_empty()).unwrap();
+    let fill = format!("fill=\"{}\"", svg_color(map.cells[first].biome.color()));
+    assert!(svg.lines().find(|l| l.starts_with("<polygon ")).unwrap().contains(&fill));
+    assert_eq!(svg_color([1.0, 0.5, -1.0, 0.0]), "#ff8000");
+  }
+
+  #[test]
+  fn invalid_json() {
+    assert!(diagram_from_json("{\"sites\": [[1.0, 2.0]").is_err());
+    let json = diagram_to_json(&voronoi(&[[1.0, 2.0], [3.0, 4.0]])).replace("\"site\":1", "\"site\":5");
+    assert_eq!(diagram_from_json(&json), Err(JsonError("index out of range".to_string())));
+  }
+}
@@ -0,0 +1,341 @@
+//! A bounded Voronoi diagram which can have sites added and removed, updating
+//! only the cells affected rather than rebuilding the whole diagram.
+//!
+//! Each cell is kept as a convex polygon which knows the site across each of
+//! its sides (see `terrain::polygon`).
+//!
+//! Adding a site walks from cell to cell towards the new site to find the
+//! cell containing it, then searches outwards for the cells with a corner
+//! closer to the new site than their own. Only those cells change - they're
+//! clipped by their bisector with the new site, and the new cell is the
+//! bounds clipped by the bisectors with each of them.
+//!
+//! Removing a site only changes the cells which shared a side with it. Their
+//! new neighbours are always among their old neighbours and the removed
+//! site's other neighbours, so each is rebuilt from just those sites.
+
+use std::mem;
+use terrain::polygon::{self, CellPolygon};
+use terrain::voronoi::VoronoiDiagram;
+
+/// A Voronoi diagram which can be updated a site at a time.
+#[derive(Clone, Debug)]
+pub struct IncrementalVoronoi {
+  /// The rectangle the cells are clipped to - X, Y, W, H
+  bounds: [f32; 4],
+  /// The position of each site, or `None` if it was removed
+  sites: Vec<Option<[f32; 2]>>,
+  /// The polygon of each site's cell. Empty for removed sites, sites outside
+  /// the bounds, and sites at the same position as an earlier site.
+  cells: Vec<CellPolygon>,
+  /// For each site, the later sites at the same position. If the site is
+  /// removed, the first of these takes over its cell.
+  duplicates: Vec<Vec<usize>>,
+  /// A site with a non-empty cell to start searches from, if there is one
+  hint: Option<usize>,
+}
+
+impl IncrementalVoronoi {
+  /// Creates a diagram, adding the given sites in order. Indices of sites are
+  /// the same as their indices in `points`.
+  /// # Params
+  /// * `points` - The initial sites
+  /// * `bounds` - The rectangle to clip the diagram to - X, Y, W, H. Sites
+  ///   outside the rectangle never have a cell.
+  pub fn new(points: &[[f32; 2]], bounds: &[f32; 4]) -> IncrementalVoronoi {
+    let mut d = IncrementalVoronoi {
+      bounds: *bounds,
+      sites: Vec::with_capacity(points.len()),
+      cells: Vec::with_capacity(points.len()),
+      duplicates: Vec::with_capacity(points.len()),
+      hint: None,
+    };
+    for &p in points { d.insert(p); }
+    d
+  }
+
+  /// Gets the number of sites ever added, including removed ones.
+  pub fn len(&self) -> usize {
+    self.sites.len()
+  }
+
+  /// Checks whether no sites have ever been added.
+  pub fn is_empty(&self) -> bool {
+    self.sites.is_empty()
+  }
+
+  /// Gets the position of a site, or `None` if it was removed.
+  pub fn site(&self, site: usize) -> Option<[f32; 2]> {
+    self.sites[site]
+  }
+
+  /// Gets the counter-clockwise polygon of a site's cell. Empty if the site
+  /// has no cell.
+  pub fn cell_polygon(&self, site: usize) -> Vec<[f32; 2]> {
+    self.cells[site].iter().map(|c| [c.pos[0] as f32, c.pos[1] as f32]).collect()
+  }
+
+  /// Gets the sites whose cells share a side with a site's cell, in
+  /// counter-clockwise order.
+  pub fn neighbours(&self, site: usize) -> Vec<usize> {
+    let mut ns : Vec<usize> = Vec::new();
+    for n in self.cells[site].iter().filter_map(|c| c.neighbour) {
+      if !ns.contains(&n) { ns.push(n); }
+    }
+    ns
+  }
+
+  /// Adds a site, updating the cells around it.
+  /// # Returns
+  /// The index of the new site.
+  pub fn insert(&mut self, p: [f32; 2]) -> usize {
+    let id = self.sites.len();
+    self.sites.push(Some(p));
+    self.cells.push(Vec::new());
+    self.duplicates.push(Vec::new());
+
+    let b = self.bounds;
+    if p[0] < b[0] || p[1] < b[1] || p[0] > b[0] + b[2] || p[1] > b[1] + b[3] { return id; }
+    let start = match self.hint {
+      Some(h) => self.walk(h, p),
+      None => {
+        self.cells[id] = polygon::rect_polygon(&self.bounds);
+        self.hint = Some(id);
+        return id;
+      }
+    };
+    if self.pos(start) == to_f64(p) {
+      self.duplicates[start].push(id);
+      return id;
+    }
+
+    // Find the cells which lose area to the new cell. These are connected, and
+    // always include the cell containing the new site.
+    let mut affected = vec![start];
+    let mut next = 0;
+    while next < affected.len() {
+      let s = affected[next];
+      next += 1;
+      for n in self.neighbours(s) {
+        if !affected.contains(&n) && self.loses_area(n, p) { affected.push(n); }
+      }
+    }
+
+    let pf = to_f64(p);
+    let mut cell = polygon::rect_polygon(&self.bounds);
+    for &s in &affected {
+      let sf = self.pos(s);
+      self.cells[s] = polygon::clip_bisector(&self.cells[s], sf, pf, 0.0, id);
+      cell = polygon::clip_bisector(&cell, pf, sf, 0.0, s);
+    }
+    self.cells[id] = cell;
+    self.hint = Some(id);
+    id
+  }
+
+  /// Removes a site, updating the cells around it. The indices of other
+  /// sites don't change.
+  /// # Returns
+  /// False if the site was already removed.
+  pub fn remove(&mut self, site: usize) -> bool {
+    let pos = match self.sites[site].take() { Some(p) => p, None => return false };
+    let cell = mem::take(&mut self.cells[site]);
+    let neighbours : Vec<usize> = {
+      let mut ns = Vec::new();
+      for n in cell.iter().filter_map(|c| c.neighbour) {
+        if !ns.contains(&n) { ns.push(n); }
+      }
+      ns
+    };
+
+    // If another site is at the same position, it takes over the cell
+    let mut duplicates = mem::take(&mut self.duplicates[site]);
+    if !duplicates.is_empty() {
+      let heir = duplicates.remove(0);
+      for &n in &neighbours {
+        for c in &mut self.cells[n] {
+          if c.neighbour == Some(site) { c.neighbour = Some(heir); }
+        }
+      }
+      self.cells[heir] = cell;
+      self.duplicates[heir] = duplicates;
+      if self.hint == Some(site) { self.hint = Some(heir); }
+      return true;
+    }
+
+    if cell.is_empty() {
+      // The site may be a duplicate of another site
+      if let Some(h) = self.hint {
+        let owner = self.walk(h, pos);
+        self.duplicates[owner].retain(|&d| d != site);
+      }
+      return true;
+    }
+
+    // Rebuild each neighbour's cell from its other neighbours and the removed
+    // site's other neighbours
+    for &s in &neighbours {
+      let mut candidates : Vec<usize> = self.neighbours(s).into_iter().filter(|&n| n != site).collect();
+      for &n in &neighbours {
+        if n != s && !candidates.contains(&n) { candidates.push(n); }
+      }
+      let sf = self.pos(s);
+      let mut cell = polygon::rect_polygon(&self.bounds);
+      for n in candidates {
+        cell = polygon::clip_bisector(&cell, sf, self.pos(n), 0.0, n);
+      }
+      self.cells[s] = cell;
+    }
+
+    if self.hint == Some(site) {
+      self.hint = neighbours.first().cloned();
+    }
+    true
+  }
+
+  /// Builds a `VoronoiDiagram` of the current sites. Removed sites are kept
+  /// so indices match, but have NaN positions and empty cells.
+  pub fn diagram(&self) -> VoronoiDiagram {
+    let sites : Vec<[f32; 2]> = self.sites.iter().map(|s| s.unwrap_or([f32::NAN; 2])).collect();
+    polygon::assemble(&sites, &self.bounds, &self.cells)
+  }
+
+  /// Gets the position of a site in doubles.
+  fn pos(&self, site: usize) -> [f64; 2] {
+    to_f64(self.sites[site].unwrap())
+  }
+
+  /// Checks whether a site's cell has a corner closer to `p` than the site.
+  fn loses_area(&self, site: usize, p: [f32; 2]) -> bool {
+    let (s, p) = (self.pos(site), to_f64(p));
+    self.cells[site].iter().any(|c| dist2(c.pos, p) < dist2(c.pos, s))
+  }
+
+  /// Walks from cell to cell towards a point, starting at a site with a
+  /// non-empty cell.
+  /// # Returns
+  /// The site nearest to `p` - the site whose cell contains `p`.
+  fn walk(&self, start: usize, p: [f32; 2]) -> usize {
+    let p = to_f64(p);
+    let mut cur = start;
+    let mut best = dist2(self.pos(cur), p);
+    loop {
+      let closer = self.cells[cur].iter().filter_map(|c| c.neighbour)
+        .map(|n| (dist2(self.pos(n), p), n))
+        .fold(None, |m: Option<(f64, usize)>, c| if c.0 < m.map_or(best, |m| m.0) { Some(c) } else { m });
+      match closer {
+        Some((d, n)) => { cur = n; best = d; }
+        None => return cur,
+      }
+    }
+  }
+}
+
+fn to_f64(p: [f32; 2]) -> [f64; 2] {
+  [p[0] as f64, p[1] as f64]
+}
+
+fn dist2(a: [f64; 2], b: [f64; 2]) -> f64 {
+  (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::Rng;
+  use terrain::seeded_rng;
+  use terrain::voronoi::voronoi_bounded;
+
+  const BOUNDS : [f32; 4] = [0.0, 0.0, 800.0, 600.0];
+
+  fn random_point<R: Rng>(rng: &mut R) -> [f32; 2] {
+    [rng.gen_range(0.0, BOUNDS[2]), rng.gen_range(0.0, BOUNDS[3])]
+  }
+
+  /// Checks the incremental diagram against a full rebuild from the sites
+  /// still in it.
+  fn check_against_rebuild(inc: &IncrementalVoronoi) {
+    let live : Vec<usize> = (0..inc.len()).filter(|&ii| inc.site(ii).is_some()).collect();
+    let points : Vec<[f32; 2]> = live.iter().map(|&ii| inc.site(ii).unwrap()).collect();
+    let full = voronoi_bounded(&points, &BOUNDS);
+    let d = inc.diagram();
+    let total : f32 = (0..points.len()).map(|ii| full.cell_area(ii)).sum();
+
+    for (k, &ii) in live.iter().enumerate() {
+      let mut expected : Vec<usize> = full.cells[k].edges.iter().map(|&e| {
+        let s = full.edges[e].sites;
+        live[if s[0] == k { s[1] } else { s[0] }]
+      }).collect();
+      expected.sort();
+      let mut actual = inc.neighbours(ii);
+      actual.sort();
+      assert_eq!(actual, expected, "neighbours of site {}", ii);
+
+      let area = d.cell_area(ii);
+      assert!((area - full.cell_area(k)).abs() <= total * 1e-5,
+              "area of site {}: {} vs {}", ii, area, full.cell_area(k));
+    }
+
+    // The assembled diagram should have the same edges
+    let edges = |d: &VoronoiDiagram, map: &dyn Fn(usize) -> usize| {
+      let mut es : Vec<(usize, usize)> = d.edges.iter()
+        .map(|e| (map(e.sites[0]).min(map(e.sites[1])), map(e.sites[0]).max(map(e.sites[1])))).collect();
+      es.sort();
+      es
+    };
+    assert_eq!(edges(&d, &|s| s), edges(&full, &|s| live[s]));
+  }
+
+  #[test]
+  fn insert_matches_rebuild() {
+    let mut rng = seeded_rng(1);
+    let mut inc = IncrementalVoronoi::new(&[], &BOUNDS);
+    for ii in 0..200 {
+      inc.insert(random_point(&mut rng));
+      if ii < 10 || ii % 20 == 0 { check_against_rebuild(&inc); }
+    }
+    check_against_rebuild(&inc);
+  }
+
+  #[test]
+  fn remove_matches_rebuild() {
+    let mut rng = seeded_rng(2);
+    let points : Vec<[f32; 2]> = (0..200).map(|_| random_point(&mut rng)).collect();
+    let mut inc = IncrementalVoronoi::new(&points, &BOUNDS);
+    for ii in 0..195 {
+      let site = (ii * 37) % 200;
+      assert!(inc.remove(site));
+      assert!(!inc.remove(site));
+      if ii % 15 == 0 || ii > 190 { check_against_rebuild(&inc); }
+    }
+    check_against_rebuild(&inc);
+  }
+
+  #[test]
+  fn mixed_updates_match_rebuild() {
+    let mut rng = seeded_rng(3);
+    let points : Vec<[f32; 2]> = (0..100).map(|_| random_point(&mut rng)).collect();
+    let mut inc = IncrementalVoronoi::new(&points, &BOUNDS);
+    for ii in 0..300 {
+      let live : Vec<usize> = (0..inc.len()).filter(|&s| inc.site(s).is_some()).collect();
+      if rng.gen_weighted_bool(2) && live.len() > 1 {
+        inc.remove(live[rng.gen_range(0, live.len())]);
+      }
+      else {
+        inc.insert(random_point(&mut rng));
+      }
+      if ii % 25 == 0 { check_against_rebuild(&inc); }
+    }
+    check_against_rebuild(&inc);
+  }
+
+  #[test]
+  fn duplicate_sites_take_over_cells() {
+    let mut inc = IncrementalVoronoi::new(&[[100.0, 100.0], [400.0, 300.0], [100.0, 100.0]], &BOUNDS);
+    assert!(inc.cell_polygon(2).is_empty());
+    assert_eq!(inc.neighbours(1), vec![0]);
+    inc.remove(0);
+    assert_eq!(inc.neighbours(1), vec![2]);
+    check_against_rebuild(&inc);
+  }
+}
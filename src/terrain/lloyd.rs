@@ -0,0 +1,54 @@
+//! Lloyd relaxation, for generating evenly sized Voronoi cells.
+//!
+//! Each iteration computes the bounded Voronoi diagram of the sites, then
+//! moves every site to the centroid of its cell. Because cells are clipped to
+//! the bounds, border sites are pulled inwards rather than drifting outwards.
+//! Repeating this converges on a centroidal Voronoi diagram, where every site
+//! is the centroid of its own cell.
+
+use rand::Rng;
+use terrain::seeded_rng;
+use terrain::voronoi::{voronoi_bounded, VoronoiDiagram};
+
+/// Relaxes a set of sites with Lloyd's algorithm.
+/// # Params
+/// * `points` - The sites to relax. These should be inside `bounds`.
+/// * `bounds` - The rectangle to clip cells to - X, Y, W, H
+/// * `max_iterations` - The maximum number of times to move the sites
+/// * `tolerance` - Stop early once no site moves further than this in an
+///   iteration
+/// # Returns
+/// The bounded Voronoi diagram of the relaxed sites.
+pub fn relax(points: &[[f32; 2]], bounds: &[f32; 4], max_iterations: usize, tolerance: f32) -> VoronoiDiagram {
+  let mut d = voronoi_bounded(points, bounds);
+  for _ in 0..max_iterations {
+    let mut max_move = 0.0f32;
+    let sites : Vec<[f32; 2]> = (0..d.sites.len()).map(|ii| {
+      // Sites with empty cells (e.g. duplicates) stay where they are
+      let c = d.cell_centroid(ii).unwrap_or(d.sites[ii]);
+      let dist = ((c[0] - d.sites[ii][0]).powi(2) + (c[1] - d.sites[ii][1]).powi(2)).sqrt();
+      max_move = max_move.max(dist);
+      c
+    }).collect();
+
+    d = voronoi_bounded(&sites, bounds);
+    if max_move <= tolerance { break; }
+  }
+  d
+}
+
+/// Generates a centroidal Voronoi diagram from uniformly random sites.
+/// # Params
+/// * `seed` - The seed used to place the sites
+/// * `count` - The number of sites
+/// * `bounds` - The rectangle to place the sites in - X, Y, W, H
+/// * `max_iterations` - The maximum number of relaxation iterations
+/// * `tolerance` - Stop relaxing once no site moves further than this
+pub fn centroidal_voronoi(seed: u64, count: usize, bounds: &[f32; 4],
+                          max_iterations: usize, tolerance: f32) -> VoronoiDiagram {
+  let mut rng = seeded_rng(seed);
+  let sites : Vec<[f32; 2]> = (0..count).map(|_| {
+    [bounds[0] + rng.gen::<f32>() * bounds[2], bounds[1] + rng.gen::<f32>() * bounds[3]]
+  }).collect();
+  relax(&sites, bounds, max_iterations, tolerance)
+}
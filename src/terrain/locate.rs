@@ -0,0 +1,255 @@
+//! Point location in Voronoi diagrams - finding which cell contains a point,
+//! and the sites nearest to a point.
+//!
+//! A point is in the cell of its nearest site, so all queries are nearest
+//! site searches over a uniform grid of buckets. Searches look at the bucket
+//! containing the point, then rings of buckets around it, stopping once no
+//! unsearched bucket could contain a closer site.
+//!
+//! For power diagrams, distances are power distances - `distance^2 - weight`
+//! - so a point is in the cell of the site with the lowest power distance.
+
+use terrain::voronoi::VoronoiDiagram;
+
+/// A spatial index over the sites of a Voronoi diagram.
+///
+/// This can be added to the world as a resource, so systems can look up the
+/// regions entities are in - see `terrain::SysLocateRegion`.
+#[derive(Clone, Debug)]
+pub struct SiteIndex {
+  sites: Vec<[f32; 2]>,
+  /// The weight of each site, or empty if the sites aren't weighted
+  weights: Vec<f32>,
+  /// The largest weight, or 0 if it's less than that
+  max_weight: f32,
+  /// Points outside these bounds aren't in any cell, if set - X, Y, W, H
+  bounds: Option<[f32; 4]>,
+  /// The min corner of the grid
+  origin: [f32; 2],
+  /// The width and height of a bucket
+  bucket_size: f32,
+  /// The number of columns and rows of buckets
+  cols: usize,
+  rows: usize,
+  /// The start of each bucket in `items`. Bucket `i` is
+  /// `items[starts[i]..starts[i+1]]`.
+  starts: Vec<usize>,
+  /// Site indices, grouped by bucket
+  items: Vec<usize>,
+}
+
+impl SiteIndex {
+  /// Creates an index over a list of sites.
+  pub fn new(sites: &[[f32; 2]]) -> SiteIndex {
+    let finite = sites.iter().filter(|p| p[0].is_finite() && p[1].is_finite());
+    let (mut min, mut max) = ([f32::MAX; 2], [f32::MIN; 2]);
+    for p in finite {
+      min = [min[0].min(p[0]), min[1].min(p[1])];
+      max = [max[0].max(p[0]), max[1].max(p[1])];
+    }
+    if min[0] > max[0] { min = [0.0; 2]; max = [0.0; 2]; }
+
+    // Size buckets to hold about 2 sites each
+    let (w, h) = ((max[0] - min[0]).max(1e-6), (max[1] - min[1]).max(1e-6));
+    let bucket_size = (w * h * 2.0 / sites.len().max(1) as f32).sqrt().max(w.max(h) / 4096.0);
+    let cols = (w / bucket_size).floor() as usize + 1;
+    let rows = (h / bucket_size).floor() as usize + 1;
+
+    let mut index = SiteIndex {
+      sites: sites.to_vec(), weights: Vec::new(), max_weight: 0.0, bounds: None, origin: min, bucket_size, cols, rows,
+      starts: vec![0; cols * rows + 1], items: Vec::new(),
+    };
+
+    // Counting sort the sites into their buckets
+    let buckets : Vec<Option<usize>> = sites.iter().map(|&p| {
+      if p[0].is_finite() && p[1].is_finite() { Some(index.bucket_of(p)) } else { None }
+    }).collect();
+    for b in buckets.iter().flatten() { index.starts[b + 1] += 1; }
+    for ii in 0..cols * rows { index.starts[ii + 1] += index.starts[ii]; }
+    let mut fill = index.starts.clone();
+    index.items = vec![0; index.starts[cols * rows]];
+    for (site, b) in buckets.iter().enumerate() {
+      if let Some(b) = *b {
+        index.items[fill[b]] = site;
+        fill[b] += 1;
+      }
+    }
+    index
+  }
+
+  /// Creates an index over weighted sites, where queries use power distance.
+  /// # Params
+  /// * `sites` - The sites
+  /// * `weights` - The weight of each site, as passed to
+  ///   `voronoi::voronoi_weighted()`
+  pub fn weighted(sites: &[[f32; 2]], weights: &[f32]) -> SiteIndex {
+    assert_eq!(sites.len(), weights.len(), "Every site needs a weight");
+    let mut index = SiteIndex::new(sites);
+    index.weights = weights.to_vec();
+    index.max_weight = weights.iter().cloned().fold(0.0, f32::max);
+    index
+  }
+
+  /// Creates an index over the sites of a diagram. For bounded diagrams,
+  /// points outside the bounds aren't in any cell, and for power diagrams,
+  /// queries use power distance.
+  pub fn from_diagram(d: &VoronoiDiagram) -> SiteIndex {
+    let mut index = match d.weights {
+      Some(ref w) => SiteIndex::weighted(&d.sites, w),
+      None => SiteIndex::new(&d.sites),
+    };
+    index.bounds = d.bounds;
+    index
+  }
+
+  /// Gets the bucket column and row containing a point, clamped to the grid.
+  fn bucket_coords(&self, p: [f32; 2]) -> (usize, usize) {
+    let cx = ((p[0] - self.origin[0]) / self.bucket_size).floor().max(0.0) as usize;
+    let cy = ((p[1] - self.origin[1]) / self.bucket_size).floor().max(0.0) as usize;
+    (cx.min(self.cols - 1), cy.min(self.rows - 1))
+  }
+
+  fn bucket_of(&self, p: [f32; 2]) -> usize {
+    let (cx, cy) = self.bucket_coords(p);
+    cy * self.cols + cx
+  }
+
+  /// Finds the cell containing a point.
+  /// # Returns
+  /// The index of the cell, or `None` if there are no sites or the point is
+  /// outside the diagram's bounds.
+  pub fn cell_at(&self, p: [f32; 2]) -> Option<usize> {
+    if let Some(b) = self.bounds {
+      if p[0] < b[0] || p[1] < b[1] || p[0] > b[0] + b[2] || p[1] > b[1] + b[3] { return None; }
+    }
+    self.nearest(p)
+  }
+
+  /// Finds the site nearest to a point - by power distance, if the sites are
+  /// weighted. If several sites are the same distance away, the one with the
+  /// lowest index is returned.
+  pub fn nearest(&self, p: [f32; 2]) -> Option<usize> {
+    self.k_nearest(p, 1).pop()
+  }
+
+  /// Finds the `k` sites nearest to a point - by power distance, if the sites
+  /// are weighted.
+  /// # Returns
+  /// Up to `k` site indices, nearest first.
+  pub fn k_nearest(&self, p: [f32; 2], k: usize) -> Vec<usize> {
+    // The best sites so far, sorted by distance then index
+    let mut best : Vec<(f32, usize)> = Vec::with_capacity(k + 1);
+    if k == 0 { return Vec::new(); }
+
+    let (cx, cy) = self.bucket_coords(p);
+    let (cx, cy) = (cx as isize, cy as isize);
+    let mut r = 0isize;
+    loop {
+      // Search the ring of buckets r away from the center bucket
+      for y in (cy - r)..(cy + r + 1) {
+        if y < 0 || y >= self.rows as isize { continue; }
+        let on_edge = y == cy - r || y == cy + r;
+        let step = if on_edge { 1 } else { (2 * r).max(1) as usize };
+        for x in ((cx - r)..(cx + r + 1)).step_by(step) {
+          if x < 0 || x >= self.cols as isize { continue; }
+          let b = y as usize * self.cols + x as usize;
+          for &site in &self.items[self.starts[b]..self.starts[b + 1]] {
+            let s = self.sites[site];
+            let w = self.weights.get(site).cloned().unwrap_or(0.0);
+            let d2 = (s[0] - p[0]).powi(2) + (s[1] - p[1]).powi(2) - w;
+            if best.len() == k && (d2, site) >= best[k - 1] { continue; }
+            let pos = best.iter().position(|&c| (d2, site) < c).unwrap_or(best.len());
+            best.insert(pos, (d2, site));
+            best.truncate(k);
+          }
+        }
+      }
+
+      // Stop if every bucket has been searched, or if the nearest point
+      // outside the searched square is further than the k-th best site, even
+      // for a site with the largest weight
+      let covered = cx - r <= 0 && cy - r <= 0 && cx + r >= self.cols as isize - 1 && cy + r >= self.rows as isize - 1;
+      if covered { break; }
+      if best.len() == k {
+        let min = [self.origin[0] + (cx - r) as f32 * self.bucket_size, self.origin[1] + (cy - r) as f32 * self.bucket_size];
+        let size = (2 * r + 1) as f32 * self.bucket_size;
+        let clearance = (p[0] - min[0]).min(p[1] - min[1]).min(min[0] + size - p[0]).min(min[1] + size - p[1]);
+        if clearance > 0.0 && best[k - 1].0 <= clearance * clearance - self.max_weight { break; }
+      }
+      r += 1;
+    }
+    best.into_iter().map(|c| c.1).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::Rng;
+  use terrain::seeded_rng;
+  use terrain::voronoi::{voronoi_bounded, voronoi_weighted};
+
+  const BOUNDS : [f32; 4] = [0.0, 0.0, 800.0, 600.0];
+
+  #[test]
+  fn matches_brute_force() {
+    let mut rng = seeded_rng(5);
+    let sites : Vec<[f32; 2]> = (0..300)
+      .map(|_| [rng.gen_range(0.0, BOUNDS[2]), rng.gen_range(0.0, BOUNDS[3])]).collect();
+    let index = SiteIndex::new(&sites);
+    for _ in 0..1000 {
+      // Include points outside the sites' bounding box
+      let p = [rng.gen_range(-200.0, BOUNDS[2] + 200.0), rng.gen_range(-200.0, BOUNDS[3] + 200.0)];
+      let dist = |s: usize| (sites[s][0] - p[0]).powi(2) + (sites[s][1] - p[1]).powi(2);
+      let mut expected : Vec<usize> = (0..sites.len()).collect();
+      expected.sort_by(|&a, &b| (dist(a), a).partial_cmp(&(dist(b), b)).unwrap());
+      assert_eq!(index.nearest(p), Some(expected[0]), "nearest to {:?}", p);
+      assert_eq!(index.k_nearest(p, 8), &expected[..8], "8 nearest to {:?}", p);
+    }
+    assert_eq!(index.k_nearest([0.0, 0.0], 0), Vec::<usize>::new());
+    assert_eq!(index.k_nearest([0.0, 0.0], 500).len(), sites.len());
+    assert_eq!(SiteIndex::new(&[]).nearest([0.0, 0.0]), None);
+  }
+
+  #[test]
+  fn cell_at_outside_bounds() {
+    let sites = [[200.0, 300.0], [600.0, 300.0], [400.0, 100.0], [400.0, 500.0]];
+    let index = SiteIndex::from_diagram(&voronoi_bounded(&sites, &BOUNDS));
+    assert_eq!(index.cell_at([100.0, 300.0]), Some(0));
+    assert_eq!(index.cell_at([800.0, 600.0]), Some(1));
+    for &p in &[[-1.0, 300.0], [900.0, 300.0], [400.0, -0.5], [400.0, 601.0]] {
+      assert_eq!(index.cell_at(p), None, "cell at {:?}", p);
+    }
+    // Unbounded indices find the nearest site anywhere
+    assert_eq!(SiteIndex::new(&sites).cell_at([900.0, 300.0]), Some(1));
+  }
+
+  #[test]
+  fn weighted_site_owns_closer_points() {
+    let sites = [[200.0, 300.0], [600.0, 300.0], [400.0, 100.0], [400.0, 500.0]];
+    let d = voronoi_weighted(&sites, &[40000.0, 0.0, 0.0, 0.0], &BOUNDS);
+    let index = SiteIndex::from_diagram(&d);
+    // The weight pushes the border between sites 0 and 1 from x = 400 out to
+    // x = 450, so site 0 owns points which are closer to site 1
+    assert_eq!(SiteIndex::new(&sites).cell_at([420.0, 300.0]), Some(1));
+    assert_eq!(index.cell_at([420.0, 300.0]), Some(0));
+    assert_eq!(index.cell_at([470.0, 300.0]), Some(1));
+    assert_eq!(index.cell_at([900.0, 300.0]), None);
+  }
+
+  #[test]
+  fn weighted_matches_brute_force() {
+    let mut rng = seeded_rng(5);
+    let sites : Vec<[f32; 2]> = (0..300)
+      .map(|_| [rng.gen_range(0.0, BOUNDS[2]), rng.gen_range(0.0, BOUNDS[3])]).collect();
+    let weights : Vec<f32> = (0..sites.len())
+      .map(|ii| if ii % 10 == 0 { rng.gen_range(0.0, 10000.0) } else { 0.0 }).collect();
+    let index = SiteIndex::weighted(&sites, &weights);
+    for _ in 0..1000 {
+      let p = [rng.gen_range(0.0, BOUNDS[2]), rng.gen_range(0.0, BOUNDS[3])];
+      let power = |s: usize| (sites[s][0] - p[0]).powi(2) + (sites[s][1] - p[1]).powi(2) - weights[s];
+      let expected = (0..sites.len()).fold(0, |n, s| if power(s) < power(n) { s } else { n });
+      assert_eq!(index.cell_at(p), Some(expected), "cell at {:?}", p);
+    }
+  }
+}
@@ -0,0 +1,337 @@
+//! Procedural map generation on top of Voronoi regions.
+//!
+//! A map is generated in stages:
+//! 1. Scatter random sites and relax them, so regions are evenly sized.
+//! 2. Assign each region an elevation from noise, falling off towards the
+//!    edge of the map so the map is an island.
+//! 3. Mark regions below sea level as water. Water connected to the edge of
+//!    the map is ocean, and the rest is lakes. Land next to the ocean is
+//!    coast.
+//! 4. Assign moisture, which is highest near fresh water.
+//! 5. Pick a biome for each region from its elevation and moisture.
+//! 6. Work out how water drains over the map, forming rivers and lakes - see
+//!    `terrain::river`.
+//!
+//! Generation is deterministic - see `TerrainMap::generate()`.
+
+use rand::Rng;
+use specs;
+use terrain::seeded_rng;
+use std::collections::VecDeque;
+use component::{CompColor, CompPolygon};
+use terrain::lloyd;
+use terrain::mesh::Mesh;
+use terrain::noise::{Fbm, Noise, Value};
+use terrain::river::Drainage;
+use terrain::voronoi::VoronoiDiagram;
+
+/// Parameters for generating a map.
+#[derive(Clone, Debug)]
+pub struct MapConfig {
+  /// The area of the map - X, Y, W, H
+  pub bounds: [f32; 4],
+  /// The number of regions in the map
+  pub num_regions: usize,
+  /// The number of Lloyd relaxation iterations used to even out regions
+  pub relax_iterations: usize,
+  /// Elevation (0 to 1) below which regions are water
+  pub sea_level: f32,
+  /// The size of the largest elevation features, in world units
+  pub feature_size: f32,
+  /// The flow needed to form a river, in region corners drained
+  pub river_flow: f32,
+}
+
+impl Default for MapConfig {
+  fn default() -> MapConfig {
+    MapConfig {
+      bounds: [0.0, 0.0, 800.0, 600.0],
+      num_regions: 500,
+      relax_iterations: 3,
+      sea_level: 0.3,
+      feature_size: 250.0,
+      river_flow: 12.0,
+    }
+  }
+}
+
+/// The type of a region, based on whether it's water.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CellKind {
+  /// Water connected to the edge of the map
+  Ocean,
+  /// Water surrounded by land
+  Lake,
+  /// Land next to the ocean
+  Coast,
+  /// Any other land
+  Land,
+}
+
+/// The biome of a region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Biome {
+  Ocean,
+  Lake,
+  Beach,
+  Snow,
+  Tundra,
+  Bare,
+  Scorched,
+  Taiga,
+  Shrubland,
+  TemperateDesert,
+  TemperateRainForest,
+  TemperateDeciduousForest,
+  Grassland,
+  TropicalRainForest,
+  TropicalSeasonalForest,
+  SubtropicalDesert,
+}
+
+impl Biome {
+  /// Picks the biome of a land region, based on a Whittaker diagram.
+  /// # Params
+  /// * `elevation` - Height above sea level, from 0 to 1
+  /// * `moisture` - Moisture, from 0 to 1
+  pub fn from_climate(elevation: f32, moisture: f32) -> Biome {
+    if elevation > 0.8 {
+      if moisture > 0.5 { Biome::Snow }
+      else if moisture > 0.33 { Biome::Tundra }
+      else if moisture > 0.16 { Biome::Bare }
+      else { Biome::Scorched }
+    }
+    else if elevation > 0.6 {
+      if moisture > 0.66 { Biome::Taiga }
+      else if moisture > 0.33 { Biome::Shrubland }
+      else { Biome::TemperateDesert }
+    }
+    else if elevation > 0.3 {
+      if moisture > 0.83 { Biome::TemperateRainForest }
+      else if moisture > 0.5 { Biome::TemperateDeciduousForest }
+      else if moisture > 0.16 { Biome::Grassland }
+      else { Biome::TemperateDesert }
+    }
+    else if moisture > 0.66 { Biome::TropicalRainForest }
+    else if moisture > 0.33 { Biome::TropicalSeasonalForest }
+    else if moisture > 0.16 { Biome::Grassland }
+    else { Biome::SubtropicalDesert }
+  }
+
+  /// The colour used to draw this biome. R, G, B, A format.
+  pub fn color(&self) -> [f32; 4] {
+    match *self {
+      Biome::Ocean => [0.27, 0.27, 0.48, 1.0],
+      Biome::Lake => [0.34, 0.45, 0.67, 1.0],
+      Biome::Beach => [0.63, 0.56, 0.47, 1.0],
+      Biome::Snow => [1.0, 1.0, 1.0, 1.0],
+      Biome::Tundra => [0.73, 0.73, 0.67, 1.0],
+      Biome::Bare => [0.53, 0.53, 0.53, 1.0],
+      Biome::Scorched => [0.33, 0.33, 0.33, 1.0],
+      Biome::Taiga => [0.6, 0.67, 0.47, 1.0],
+      Biome::Shrubland => [0.53, 0.6, 0.47, 1.0],
+      Biome::TemperateDesert => [0.79, 0.82, 0.61, 1.0],
+      Biome::TemperateRainForest => [0.27, 0.53, 0.33, 1.0],
+      Biome::TemperateDeciduousForest => [0.4, 0.58, 0.35, 1.0],
+      Biome::Grassland => [0.53, 0.67, 0.33, 1.0],
+      Biome::TropicalRainForest => [0.2, 0.47, 0.33, 1.0],
+      Biome::TropicalSeasonalForest => [0.33, 0.6, 0.27, 1.0],
+      Biome::SubtropicalDesert => [0.82, 0.73, 0.55, 1.0],
+    }
+  }
+}
+
+/// The generated attributes of a single region.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TerrainCell {
+  /// Elevation from 0 to 1. Regions below the map's sea level are water.
+  pub elevation: f32,
+  /// Moisture from 0 to 1
+  pub moisture: f32,
+  pub kind: CellKind,
+  pub biome: Biome,
+}
+
+/// A generated map. Region `i` is cell `i` of the diagram and face `i` of the
+/// mesh.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TerrainMap {
+  /// The bounded Voronoi diagram of the regions
+  pub diagram: VoronoiDiagram,
+  /// The regions as a half-edge mesh, for neighbour queries
+  pub mesh: Mesh,
+  /// The attributes of each region
+  pub cells: Vec<TerrainCell>,
+  /// Elevation below which regions are water
+  pub sea_level: f32,
+  /// The drainage of the map, including its rivers. Corner `i` is vertex `i`
+  /// of the mesh.
+  pub drainage: Drainage,
+}
+
+impl TerrainMap {
+  /// Generates a new map.
+  ///
+  /// The same seed and config always produce a bit-identical map with the
+  /// same version of the game. Generation only uses basic float arithmetic
+  /// plus `sqrt`, `powi` and `atan2`, so this also holds between builds for
+  /// the same target, but isn't guaranteed between targets, as `powi` and
+  /// `atan2` may be implemented differently.
+  /// # Params
+  /// * `config` - The parameters of the map
+  /// * `seed` - The seed to generate the map from
+  pub fn generate(config: &MapConfig, seed: u64) -> TerrainMap {
+    let mut rng = seeded_rng(seed);
+    let b = config.bounds;
+    let diagram = lloyd::centroidal_voronoi(rng.next_u64(), config.num_regions, &b, config.relax_iterations, 0.0);
+    let mesh = Mesh::from_voronoi(&diagram);
+    let n = diagram.sites.len();
+
+    // Elevation - noise, multiplied by a falloff so the edges of the map are
+    // underwater
+    let noise = Fbm::new(Value::from_rng(&mut rng), 4);
+    let mut elevation : Vec<f32> = diagram.sites.iter().map(|s| {
+      let (nx, ny) = ((s[0] - b[0]) / b[2] * 2.0 - 1.0, (s[1] - b[1]) / b[3] * 2.0 - 1.0);
+      let falloff = 1.0 - (nx*nx + ny*ny).min(1.0);
+      noise.get(s[0] / config.feature_size, s[1] / config.feature_size) * falloff.sqrt()
+    }).collect();
+    // Stretch so the highest region has an elevation of 1
+    let max_elevation = elevation.iter().fold(0.0f32, |m, &e| m.max(e));
+    if max_elevation > 0.0 {
+      for e in &mut elevation { *e /= max_elevation; }
+    }
+
+    // Water below sea level is ocean if it's connected to the edge of the map,
+    // so flood fill from the regions on the edge
+    let is_water = |ii: usize| elevation[ii] < config.sea_level;
+    let mut kind : Vec<CellKind> = (0..n).map(|ii| if is_water(ii) { CellKind::Lake } else { CellKind::Land }).collect();
+    let mut queue : VecDeque<usize> = (0..n).filter(|&ii| {
+      is_water(ii) && mesh.face_half_edges(ii).any(|h| mesh.half_edges[h].twin.is_none())
+    }).collect();
+    for &ii in &queue { kind[ii] = CellKind::Ocean; }
+    while let Some(ii) = queue.pop_front() {
+      for nb in mesh.face_neighbours(ii) {
+        if kind[nb] == CellKind::Lake {
+          kind[nb] = CellKind::Ocean;
+          queue.push_back(nb);
+        }
+      }
+    }
+    for ii in 0..n {
+      if kind[ii] == CellKind::Land && mesh.face_neighbours(ii).any(|nb| kind[nb] == CellKind::Ocean) {
+        kind[ii] = CellKind::Coast;
+      }
+    }
+
+    // Moisture - falls off with distance (in regions) from water, with lakes
+    // and coasts counting as the wettest
+    let mut dist = vec![None; n];
+    let mut queue : VecDeque<usize> = (0..n).filter(|&ii| kind[ii] == CellKind::Lake || kind[ii] == CellKind::Coast).collect();
+    for &ii in &queue { dist[ii] = Some(0); }
+    while let Some(ii) = queue.pop_front() {
+      for nb in mesh.face_neighbours(ii) {
+        if dist[nb].is_none() && kind[nb] != CellKind::Ocean {
+          dist[nb] = dist[ii].map(|d| d + 1);
+          queue.push_back(nb);
+        }
+      }
+    }
+    let moisture_noise = Fbm::new(Value::from_rng(&mut rng), 2);
+    let moisture : Vec<f32> = (0..n).map(|ii| {
+      let s = diagram.sites[ii];
+      let from_water = dist[ii].map_or(0.0, |d| 0.85f32.powi(d));
+      let noise = moisture_noise.get(s[0] / config.feature_size, s[1] / config.feature_size);
+      (from_water * 0.75 + noise * 0.25).clamp(0.0, 1.0)
+    }).collect();
+
+    // Biomes
+    let cells : Vec<TerrainCell> = (0..n).map(|ii| {
+      let land_elevation = (elevation[ii] - config.sea_level) / (1.0 - config.sea_level);
+      let biome = match kind[ii] {
+        CellKind::Ocean => Biome::Ocean,
+        CellKind::Lake => Biome::Lake,
+        CellKind::Coast => Biome::Beach,
+        CellKind::Land => Biome::from_climate(land_elevation, moisture[ii]),
+      };
+      TerrainCell { elevation: elevation[ii], moisture: moisture[ii], kind: kind[ii], biome }
+    }).collect();
+
+    let drainage = Drainage::new(&mesh, &cells, config.river_flow);
+
+    TerrainMap { diagram, mesh, cells, sea_level: config.sea_level, drainage }
+  }
+
+  /// Creates an entity for each region of the map, with a `CompPolygon` of
+  /// its shape and a `CompColor` of its biome, so the regions are drawn by the
+  /// `SysRenderer`.
+  /// # Returns
+  /// The entities created, indexed by region.
+  pub fn spawn(&self, world: &mut specs::World) -> Vec<specs::Entity> {
+    (0..self.cells.len()).map(|ii| {
+      world.create_now()
+        .with(CompPolygon(self.diagram.cell_polygon(ii)))
+        .with(CompColor(self.cells[ii].biome.color()))
+        .build()
+    }).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// FNV-1a hash of everything generated in a map. Floats are hashed by their
+  /// bits, so any change to the output changes the hash.
+  fn hash_map(m: &TerrainMap) -> u64 {
+    let mut h = 0xcbf2_9ce4_8422_2325u64;
+    {
+      let mut add = |x: u32| {
+        for b in 0..4 {
+          h ^= ((x >> (b * 8)) & 0xff) as u64;
+          h = h.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+      };
+      for p in m.diagram.sites.iter().chain(&m.diagram.vertices) {
+        add(p[0].to_bits());
+        add(p[1].to_bits());
+      }
+      for c in &m.diagram.cells {
+        for &v in &c.vertices { add(v as u32); }
+      }
+      for c in &m.cells {
+        add(c.elevation.to_bits());
+        add(c.moisture.to_bits());
+        add(c.kind as u32);
+        add(c.biome as u32);
+      }
+      let d = &m.drainage;
+      for ii in 0..d.elevation.len() {
+        add(d.elevation[ii].to_bits());
+        add(d.water_level[ii].to_bits());
+        add(d.downstream[ii].map_or(u32::MAX, |c| c as u32));
+        add(d.flow[ii].to_bits());
+        add(d.lake[ii] as u32 | ((d.river[ii] as u32) << 1));
+      }
+      for r in &d.rivers {
+        add(r.corners.len() as u32);
+        for &c in &r.corners { add(c as u32); }
+      }
+    }
+    h
+  }
+
+  #[test]
+  fn same_seed_same_map() {
+    let config = MapConfig::default();
+    assert_eq!(TerrainMap::generate(&config, 1234), TerrainMap::generate(&config, 1234));
+    assert!(TerrainMap::generate(&config, 1234) != TerrainMap::generate(&config, 1235));
+  }
+
+  /// If this fails, map generation has changed and maps from old seeds will
+  /// differ. Update the hash only if that's intended.
+  #[test]
+  fn seed_regression() {
+    let m = TerrainMap::generate(&MapConfig::default(), 42);
+    assert_eq!(hash_map(&m), 16148572170367119748);
+  }
+}
@@ -0,0 +1,178 @@
+//! A half-edge mesh (doubly-connected edge list) representation of terrain
+//! regions, for region-level queries shared between generators.
+//!
+//! Each face is a region, each region border is a pair of twin half-edges
+//! going in opposite directions, and each half-edge knows the face it borders.
+//! Half-edges on the outside border of the mesh have no twin.
+
+use std::collections::HashMap;
+use terrain::voronoi::VoronoiDiagram;
+
+/// A half-edge mesh.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mesh {
+  pub vertices: Vec<MeshVertex>,
+  pub half_edges: Vec<HalfEdge>,
+  pub faces: Vec<Face>,
+}
+
+/// A vertex of a mesh - a corner shared by 1 or more faces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshVertex {
+  pub pos: [f32; 2],
+  /// A half-edge starting at this vertex, if any
+  pub half_edge: Option<usize>,
+}
+
+/// One side of an edge between 2 vertices. The face it borders is on its left,
+/// so going around a face via `next` is counter-clockwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HalfEdge {
+  /// The vertex this half-edge starts at
+  pub origin: usize,
+  /// The half-edge going the other way along the same edge, bordering the
+  /// neighbouring face. `None` on the border of the mesh.
+  pub twin: Option<usize>,
+  /// The next half-edge around the face
+  pub next: usize,
+  /// The previous half-edge around the face
+  pub prev: usize,
+  /// The face this half-edge borders
+  pub face: usize,
+}
+
+/// A face of a mesh - a single region.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Face {
+  /// The site this face was generated from
+  pub site: [f32; 2],
+  /// A half-edge bordering this face, or `None` if the face is empty
+  pub half_edge: Option<usize>,
+}
+
+impl Mesh {
+  /// Creates a mesh from a bounded Voronoi diagram, with one face per cell.
+  /// Face and vertex indices are the same as the diagram's cell and vertex
+  /// indices.
+  /// # Params
+  /// * `d` - The diagram. This should be from `voronoi_bounded()`, as cells
+  ///   without polygons become empty faces.
+  pub fn from_voronoi(d: &VoronoiDiagram) -> Mesh {
+    let mut vertices : Vec<MeshVertex> = d.vertices.iter()
+      .map(|&pos| MeshVertex { pos, half_edge: None }).collect();
+    let mut half_edges = Vec::new();
+    let mut faces = Vec::with_capacity(d.cells.len());
+
+    // Half-edges by their start and end vertices, used to find twins
+    let mut by_ends = HashMap::new();
+    for (ii, cell) in d.cells.iter().enumerate() {
+      let n = cell.vertices.len();
+      let first = half_edges.len();
+      faces.push(Face { site: d.sites[cell.site], half_edge: if n > 0 { Some(first) } else { None } });
+      for jj in 0..n {
+        let (origin, dest) = (cell.vertices[jj], cell.vertices[(jj + 1) % n]);
+        half_edges.push(HalfEdge {
+          origin,
+          twin: None,
+          next: first + (jj + 1) % n,
+          prev: first + (jj + n - 1) % n,
+          face: ii,
+        });
+        by_ends.insert((origin, dest), first + jj);
+        if vertices[origin].half_edge.is_none() { vertices[origin].half_edge = Some(first + jj); }
+      }
+    }
+
+    // Link up twins
+    for ii in 0..half_edges.len() {
+      let dest = half_edges[half_edges[ii].next].origin;
+      half_edges[ii].twin = by_ends.get(&(dest, half_edges[ii].origin)).cloned();
+    }
+
+    Mesh { vertices, half_edges, faces }
+  }
+
+  /// Gets the vertex a half-edge ends at.
+  pub fn dest(&self, half_edge: usize) -> usize {
+    self.half_edges[self.half_edges[half_edge].next].origin
+  }
+
+  /// Iterates over the half-edges around a face, counter-clockwise.
+  pub fn face_half_edges<'a>(&'a self, face: usize) -> FaceHalfEdges<'a> {
+    let start = self.faces[face].half_edge;
+    FaceHalfEdges { mesh: self, start, next: start }
+  }
+
+  /// Iterates over the vertices around a face, counter-clockwise.
+  pub fn face_vertices<'a>(&'a self, face: usize) -> impl Iterator<Item = usize> + 'a {
+    self.face_half_edges(face).map(move |h| self.half_edges[h].origin)
+  }
+
+  /// Iterates over the faces sharing an edge with a face, counter-clockwise.
+  pub fn face_neighbours<'a>(&'a self, face: usize) -> impl Iterator<Item = usize> + 'a {
+    self.face_half_edges(face).filter_map(move |h| self.half_edges[h].twin).map(move |t| self.half_edges[t].face)
+  }
+
+  /// Iterates over the half-edges starting at a vertex.
+  pub fn vertex_half_edges<'a>(&'a self, vertex: usize) -> VertexHalfEdges<'a> {
+    let start = self.vertices[vertex].half_edge;
+    VertexHalfEdges { mesh: self, start: start.unwrap_or(0), next: start, forward: true }
+  }
+
+  /// Iterates over the faces touching a vertex.
+  pub fn vertex_faces<'a>(&'a self, vertex: usize) -> impl Iterator<Item = usize> + 'a {
+    self.vertex_half_edges(vertex).map(move |h| self.half_edges[h].face)
+  }
+}
+
+/// Iterator over the half-edges around a face. See `Mesh::face_half_edges()`.
+pub struct FaceHalfEdges<'a> {
+  mesh: &'a Mesh,
+  start: Option<usize>,
+  next: Option<usize>,
+}
+
+impl<'a> Iterator for FaceHalfEdges<'a> {
+  type Item = usize;
+  fn next(&mut self) -> Option<usize> {
+    let h = self.next?;
+    let next = self.mesh.half_edges[h].next;
+    self.next = if Some(next) == self.start { None } else { Some(next) };
+    Some(h)
+  }
+}
+
+/// Iterator over the half-edges starting at a vertex. See
+/// `Mesh::vertex_half_edges()`.
+pub struct VertexHalfEdges<'a> {
+  mesh: &'a Mesh,
+  start: usize,
+  next: Option<usize>,
+  /// Whether we're still rotating in the first direction. If a vertex is on
+  /// the border of the mesh, we hit the border going one way, then go back to
+  /// the start and rotate the other way.
+  forward: bool,
+}
+
+impl<'a> Iterator for VertexHalfEdges<'a> {
+  type Item = usize;
+  fn next(&mut self) -> Option<usize> {
+    let h = self.next?;
+    let hes = &self.mesh.half_edges;
+    if self.forward {
+      // The twin of the previous half-edge also starts at this vertex
+      self.next = match hes[hes[h].prev].twin {
+        Some(t) if t == self.start => None,
+        Some(t) => Some(t),
+        None => {
+          self.forward = false;
+          hes[self.start].twin.map(|t| hes[t].next)
+        }
+      };
+    }
+    else {
+      self.next = hes[h].twin.map(|t| hes[t].next);
+    }
+    Some(h)
+  }
+}
@@ -1 +1,46 @@
+//! Terrain generation. Every generator takes a `u64` seed, and the same seed
+//! always produces the same output for the same version of the game.
+
 pub mod voronoi;
+pub mod delaunay;
+pub mod lloyd;
+pub mod mesh;
+pub mod map;
+pub mod locate;
+pub mod polygon;
+pub mod incremental;
+pub mod diagram;
+pub mod river;
+pub mod noise;
+pub mod road;
+pub mod export;
+
+/// A module containing the terrain systems, which run as part of the ECS.
+mod system;
+
+pub use self::system::{SysLocateRegion, SysTerrain};
+
+use rand::{SeedableRng, XorShiftRng};
+
+/// Creates the random number generator used by terrain generators from a
+/// seed.
+///
+/// `XorShiftRng` is used rather than `StdRng`, because its algorithm is fixed,
+/// whereas `StdRng` differs between platforms and may change between versions
+/// of `rand`.
+pub fn seeded_rng(seed: u64) -> XorShiftRng {
+  // Spread the seed over the generator's state with splitmix64, as xorshift
+  // needs a non-zero state and gives poor output for states with few bits set
+  let mut x = seed;
+  let mut next = || {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+  };
+  let (a, b) = (next(), next());
+  let mut state = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+  if state == [0; 4] { state[0] = 1; }
+  XorShiftRng::from_seed(state)
+}
@@ -0,0 +1,363 @@
+//! Seeded 2D noise functions, for heightmaps, moisture and other terrain
+//! attributes.
+//!
+//! Every generator implements `Noise`, so they can be combined - `Fbm` sums
+//! octaves of any noise, and `Ridged` turns any noise into sharp ridges.
+//! `draw_noise()` draws a noise field for tweaking parameters by eye.
+//!
+//! Like the rest of `terrain`, the same seed always gives the same noise.
+
+use rand::Rng;
+use renderer::RendererController;
+use terrain::locate::SiteIndex;
+use terrain::seeded_rng;
+
+/// A 2D noise function.
+pub trait Noise {
+  /// Samples the noise at a point.
+  fn get(&self, x: f32, y: f32) -> f32;
+}
+
+impl<N: Noise> Noise for &N {
+  fn get(&self, x: f32, y: f32) -> f32 { (**self).get(x, y) }
+}
+
+/// Creates a permutation table of 0 to 255, repeated twice so lookups of
+/// `perm[perm[x] + y]` don't need wrapping.
+fn permutation<R: Rng>(rng: &mut R) -> Vec<usize> {
+  let mut perm : Vec<usize> = (0..256).collect();
+  rng.shuffle(&mut perm);
+  let repeat = perm.clone();
+  perm.extend(repeat);
+  perm
+}
+
+/// Smooth value noise - random values on a grid, interpolated between.
+/// Values are from 0 to 1.
+#[derive(Clone, Debug)]
+pub struct Value {
+  /// Random value for each hashed grid point
+  values: Vec<f32>,
+  /// Permutation table used to hash grid points, repeated twice
+  perm: Vec<usize>,
+}
+
+impl Value {
+  /// Creates value noise from a seed.
+  pub fn new(seed: u64) -> Value {
+    Value::from_rng(&mut seeded_rng(seed))
+  }
+
+  /// Creates value noise, taking random numbers from an existing generator.
+  pub fn from_rng<R: Rng>(rng: &mut R) -> Value {
+    let values = (0..256).map(|_| rng.gen::<f32>()).collect();
+    Value { values, perm: permutation(rng) }
+  }
+}
+
+impl Noise for Value {
+  fn get(&self, x: f32, y: f32) -> f32 {
+    let (fx, fy) = (x.floor(), y.floor());
+    let (ix, iy) = ((fx as i32 & 255) as usize, (fy as i32 & 255) as usize);
+    let hash = |dx: usize, dy: usize| self.values[self.perm[self.perm[ix + dx] + iy + dy]];
+    // Smoothstep the fractional part so the noise has no visible grid lines
+    let (tx, ty) = (x - fx, y - fy);
+    let (sx, sy) = (tx*tx*(3.0 - 2.0*tx), ty*ty*(3.0 - 2.0*ty));
+    let top = hash(0, 0) + (hash(1, 0) - hash(0, 0)) * sx;
+    let bottom = hash(0, 1) + (hash(1, 1) - hash(0, 1)) * sx;
+    top + (bottom - top) * sy
+  }
+}
+
+/// Perlin gradient noise. Values are from about -1 to 1, and are 0 at
+/// integer coordinates.
+#[derive(Clone, Debug)]
+pub struct Perlin {
+  perm: Vec<usize>,
+}
+
+impl Perlin {
+  /// Creates Perlin noise from a seed.
+  pub fn new(seed: u64) -> Perlin {
+    Perlin { perm: permutation(&mut seeded_rng(seed)) }
+  }
+}
+
+impl Noise for Perlin {
+  fn get(&self, x: f32, y: f32) -> f32 {
+    const GRADIENTS : [[f32; 2]; 8] = [[1.0, 1.0], [-1.0, 1.0], [1.0, -1.0], [-1.0, -1.0],
+                                       [1.0, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0]];
+    let (fx, fy) = (x.floor(), y.floor());
+    let (ix, iy) = ((fx as i32 & 255) as usize, (fy as i32 & 255) as usize);
+    let (tx, ty) = (x - fx, y - fy);
+    // The dot product of a grid point's gradient and the offset to it
+    let grad = |dx: usize, dy: usize| {
+      let g = GRADIENTS[self.perm[self.perm[ix + dx] + iy + dy] & 7];
+      g[0] * (tx - dx as f32) + g[1] * (ty - dy as f32)
+    };
+    // Quintic fade, so the noise's 2nd derivative is continuous
+    let fade = |t: f32| t*t*t*(t*(t*6.0 - 15.0) + 10.0);
+    let (sx, sy) = (fade(tx), fade(ty));
+    let top = grad(0, 0) + (grad(1, 0) - grad(0, 0)) * sx;
+    let bottom = grad(0, 1) + (grad(1, 1) - grad(0, 1)) * sx;
+    top + (bottom - top) * sy
+  }
+}
+
+/// OpenSimplex noise - gradient noise on a triangular grid, which has fewer
+/// directional artifacts than Perlin noise. Values are from about -1 to 1.
+#[derive(Clone, Debug)]
+pub struct OpenSimplex {
+  perm: Vec<usize>,
+}
+
+impl OpenSimplex {
+  /// Skews a point from the triangular grid onto the square grid
+  const STRETCH : f64 = -0.211_324_865_405_187;
+  /// Skews a point from the square grid back onto the triangular grid
+  const SQUISH : f64 = 0.366_025_403_784_439;
+  /// Scales the output to about -1 to 1
+  const NORM : f64 = 47.0;
+
+  /// Creates OpenSimplex noise from a seed.
+  pub fn new(seed: u64) -> OpenSimplex {
+    OpenSimplex { perm: permutation(&mut seeded_rng(seed)) }
+  }
+
+  /// The contribution of a lattice point to a sample, given the offset from
+  /// the lattice point to the sample.
+  fn contribution(&self, xsb: i64, ysb: i64, dx: f64, dy: f64) -> f64 {
+    const GRADIENTS : [f64; 16] = [5.0, 2.0, 2.0, 5.0, -5.0, 2.0, -2.0, 5.0,
+                                   5.0, -2.0, 2.0, -5.0, -5.0, -2.0, -2.0, -5.0];
+    let attn = 2.0 - dx*dx - dy*dy;
+    if attn <= 0.0 { return 0.0; }
+    let ii = self.perm[self.perm[(xsb & 255) as usize] + (ysb & 255) as usize] & 14;
+    attn.powi(4) * (GRADIENTS[ii] * dx + GRADIENTS[ii + 1] * dy)
+  }
+}
+
+impl Noise for OpenSimplex {
+  fn get(&self, x: f32, y: f32) -> f32 {
+    let (sq, (x, y)) = (OpenSimplex::SQUISH, (x as f64, y as f64));
+    // Find the rhombus (pair of triangles) containing the point
+    let stretch = (x + y) * OpenSimplex::STRETCH;
+    let (xs, ys) = (x + stretch, y + stretch);
+    let (mut xsb, mut ysb) = (xs.floor() as i64, ys.floor() as i64);
+    let squish = (xsb + ysb) as f64 * sq;
+    let (xins, yins) = (xs - xsb as f64, ys - ysb as f64);
+    let in_sum = xins + yins;
+    let (mut dx0, mut dy0) = (x - (xsb as f64 + squish), y - (ysb as f64 + squish));
+
+    // The (1, 0) and (0, 1) corners always contribute
+    let mut value = self.contribution(xsb + 1, ysb, dx0 - 1.0 - sq, dy0 - sq) +
+                    self.contribution(xsb, ysb + 1, dx0 - sq, dy0 - 1.0 - sq);
+
+    // Pick the extra lattice point which can contribute, depending on which
+    // triangle of the rhombus the point is in
+    let ext;
+    if in_sum <= 1.0 {
+      let zins = 1.0 - in_sum;
+      ext = if zins > xins || zins > yins {
+        if xins > yins { (xsb + 1, ysb - 1, dx0 - 1.0, dy0 + 1.0) }
+        else { (xsb - 1, ysb + 1, dx0 + 1.0, dy0 - 1.0) }
+      }
+      else { (xsb + 1, ysb + 1, dx0 - 1.0 - 2.0*sq, dy0 - 1.0 - 2.0*sq) };
+    }
+    else {
+      let zins = 2.0 - in_sum;
+      ext = if zins < xins || zins < yins {
+        if xins > yins { (xsb + 2, ysb, dx0 - 2.0 - 2.0*sq, dy0 - 2.0*sq) }
+        else { (xsb, ysb + 2, dx0 - 2.0*sq, dy0 - 2.0 - 2.0*sq) }
+      }
+      else { (xsb, ysb, dx0, dy0) };
+      xsb += 1;
+      ysb += 1;
+      dx0 -= 1.0 + 2.0*sq;
+      dy0 -= 1.0 + 2.0*sq;
+    }
+
+    // The (0, 0) or (1, 1) corner, then the extra point
+    value += self.contribution(xsb, ysb, dx0, dy0);
+    value += self.contribution(ext.0, ext.1, ext.2, ext.3);
+    (value / OpenSimplex::NORM) as f32
+  }
+}
+
+/// Worley (cellular) noise - the distance to the nearest of a set of
+/// scattered sites. The sites are the same as Voronoi sites, so the noise's
+/// creases are on the edges of their Voronoi diagram.
+///
+/// Unlike the other noise functions, this only covers a limited area.
+#[derive(Clone, Debug)]
+pub struct Worley {
+  sites: Vec<[f32; 2]>,
+  index: SiteIndex,
+  /// The average distance between sites, used to scale the output
+  spacing: f32,
+}
+
+impl Worley {
+  /// Creates Worley noise with randomly scattered sites.
+  /// # Params
+  /// * `seed` - The seed to scatter the sites with
+  /// * `count` - The number of sites
+  /// * `bounds` - The area to scatter the sites over - X, Y, W, H
+  pub fn new(seed: u64, count: usize, bounds: &[f32; 4]) -> Worley {
+    let mut rng = seeded_rng(seed);
+    let sites = (0..count).map(|_| {
+      [bounds[0] + rng.gen::<f32>() * bounds[2], bounds[1] + rng.gen::<f32>() * bounds[3]]
+    }).collect();
+    let mut w = Worley::from_sites(sites);
+    w.spacing = (bounds[2] * bounds[3] / count.max(1) as f32).sqrt();
+    w
+  }
+
+  /// Creates Worley noise from existing sites, e.g. the sites of a terrain
+  /// map.
+  pub fn from_sites(sites: Vec<[f32; 2]>) -> Worley {
+    let index = SiteIndex::new(&sites);
+    let (mut min, mut max) = ([f32::MAX; 2], [f32::MIN; 2]);
+    for p in &sites {
+      min = [min[0].min(p[0]), min[1].min(p[1])];
+      max = [max[0].max(p[0]), max[1].max(p[1])];
+    }
+    let spacing = if sites.is_empty() { 1.0 } else {
+      ((max[0] - min[0]) * (max[1] - min[1]) / sites.len() as f32).sqrt().max(1e-6)
+    };
+    Worley { sites, index, spacing }
+  }
+
+  /// Gets the distances to the nearest (F1) and 2nd nearest (F2) sites. The
+  /// distance is infinite if there aren't enough sites.
+  pub fn distances(&self, x: f32, y: f32) -> [f32; 2] {
+    let mut out = [f32::INFINITY; 2];
+    for (ii, s) in self.index.k_nearest([x, y], 2).into_iter().enumerate() {
+      let p = self.sites[s];
+      out[ii] = ((p[0] - x).powi(2) + (p[1] - y).powi(2)).sqrt();
+    }
+    out
+  }
+
+  /// Gets the site nearest to a point, which is the Voronoi cell containing
+  /// it. Useful for giving each cell a random value.
+  pub fn cell(&self, x: f32, y: f32) -> Option<usize> {
+    self.index.nearest([x, y])
+  }
+}
+
+impl Noise for Worley {
+  /// The distance to the nearest site, divided by the average distance
+  /// between sites. Usually from 0 to 1.
+  fn get(&self, x: f32, y: f32) -> f32 {
+    self.distances(x, y)[0] / self.spacing
+  }
+}
+
+/// Scales the coordinates passed to a noise, so its features are a given
+/// size in world units.
+#[derive(Clone, Debug)]
+pub struct Scale<N> {
+  pub noise: N,
+  /// The size of 1 unit of the noise, in world units
+  pub size: f32,
+}
+
+impl<N: Noise> Noise for Scale<N> {
+  fn get(&self, x: f32, y: f32) -> f32 {
+    self.noise.get(x / self.size, y / self.size)
+  }
+}
+
+/// Fractal Brownian motion - sums octaves of a noise, each at a higher
+/// frequency and lower amplitude than the last. The output has the same range
+/// as the noise.
+#[derive(Clone, Debug)]
+pub struct Fbm<N> {
+  pub noise: N,
+  pub octaves: u32,
+  /// The frequency multiplier between octaves
+  pub lacunarity: f32,
+  /// The amplitude multiplier between octaves
+  pub gain: f32,
+}
+
+impl<N: Noise> Fbm<N> {
+  /// Creates fBm with each octave at double the frequency and half the
+  /// amplitude of the last.
+  pub fn new(noise: N, octaves: u32) -> Fbm<N> {
+    Fbm { noise, octaves, lacunarity: 2.0, gain: 0.5 }
+  }
+}
+
+impl<N: Noise> Noise for Fbm<N> {
+  fn get(&self, x: f32, y: f32) -> f32 {
+    let (mut sum, mut amp, mut freq, mut total) = (0.0, 1.0, 1.0, 0.0);
+    for _ in 0..self.octaves {
+      sum += self.noise.get(x * freq, y * freq) * amp;
+      total += amp;
+      amp *= self.gain;
+      freq *= self.lacunarity;
+    }
+    if total > 0.0 { sum / total } else { 0.0 }
+  }
+}
+
+/// Ridged multifractal noise - like fBm, but each octave is folded so its
+/// zero crossings become sharp ridges, and octaves are weighted by the
+/// octaves before them so valleys stay smooth. Good for mountain ranges.
+/// Values are from 0 to 1, for noise from -1 to 1.
+#[derive(Clone, Debug)]
+pub struct Ridged<N> {
+  pub noise: N,
+  pub octaves: u32,
+  /// The frequency multiplier between octaves
+  pub lacunarity: f32,
+  /// The amplitude multiplier between octaves
+  pub gain: f32,
+}
+
+impl<N: Noise> Ridged<N> {
+  /// Creates ridged noise with each octave at double the frequency and half
+  /// the amplitude of the last.
+  pub fn new(noise: N, octaves: u32) -> Ridged<N> {
+    Ridged { noise, octaves, lacunarity: 2.0, gain: 0.5 }
+  }
+}
+
+impl<N: Noise> Noise for Ridged<N> {
+  fn get(&self, x: f32, y: f32) -> f32 {
+    let (mut sum, mut amp, mut freq, mut total, mut weight) = (0.0, 1.0, 1.0, 0.0, 1.0);
+    for _ in 0..self.octaves {
+      let signal = (1.0 - self.noise.get(x * freq, y * freq).abs()).max(0.0);
+      let signal = signal * signal * weight;
+      weight = (signal * 2.0).clamp(0.0, 1.0);
+      sum += signal * amp;
+      total += amp;
+      amp *= self.gain;
+      freq *= self.lacunarity;
+    }
+    if total > 0.0 { sum / total } else { 0.0 }
+  }
+}
+
+/// Draws a noise field for debugging, as a grid of grey squares.
+/// # Params
+/// * `noise` - The noise to draw
+/// * `bounds` - The area to draw - X, Y, W, H. The noise is sampled at world
+///   coordinates, so wrap it in a `Scale` first.
+/// * `cell_size` - The size of each square. Keep this large enough that the
+///   grid fits in the renderer's VBO.
+/// * `range` - The noise values drawn as black and white
+/// * `r` - The renderer controller to send the geometry to
+pub fn draw_noise<N: Noise>(noise: &N, bounds: &[f32; 4], cell_size: f32, range: [f32; 2], r: &RendererController) {
+  let cols = (bounds[2] / cell_size).ceil() as usize;
+  let rows = (bounds[3] / cell_size).ceil() as usize;
+  for y in 0..rows {
+    for x in 0..cols {
+      let (px, py) = (bounds[0] + x as f32 * cell_size, bounds[1] + y as f32 * cell_size);
+      let v = noise.get(px + cell_size / 2.0, py + cell_size / 2.0);
+      let c = ((v - range[0]) / (range[1] - range[0])).clamp(0.0, 1.0);
+      r.rect(&[px, py, cell_size, cell_size], &[c, c, c, 1.0]);
+    }
+  }
+}
@@ -0,0 +1,177 @@
+//! Building Voronoi cells one at a time, as convex polygons clipped by
+//! half-planes.
+//!
+//! Fortune's algorithm builds a whole diagram at once. Some diagrams (power
+//! diagrams, diagrams updated site by site) are easier to build by starting
+//! each cell as the bounding rectangle and cutting away the parts closer to
+//! other sites. The polygons remember which site is across each side, so they
+//! can be assembled back into a `VoronoiDiagram`.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use terrain::delaunay::Delaunay;
+use terrain::voronoi::{Cell, Edge, VoronoiDiagram};
+
+/// A corner of a cell polygon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Corner {
+  pub pos: [f64; 2],
+  /// The site across the side from this corner to the next one, or `None`
+  /// if the side is on the bounding rectangle.
+  pub neighbour: Option<usize>,
+}
+
+/// A convex cell polygon, counter-clockwise.
+pub type CellPolygon = Vec<Corner>;
+
+/// Creates a polygon covering a rectangle - X, Y, W, H.
+pub fn rect_polygon(bounds: &[f32; 4]) -> CellPolygon {
+  let (x, y, w, h) = (bounds[0] as f64, bounds[1] as f64, bounds[2] as f64, bounds[3] as f64);
+  [[x, y], [x + w, y], [x + w, y + h], [x, y + h]].iter()
+    .map(|&pos| Corner { pos, neighbour: None }).collect()
+}
+
+/// Clips a polygon to the half-plane `dot(x, n) <= c`.
+/// # Params
+/// * `poly` - The polygon to clip
+/// * `n` - The normal of the half-plane's boundary, pointing out of it
+/// * `c` - The offset of the boundary
+/// * `neighbour` - The site across the new side created by the clip
+pub fn clip(poly: &[Corner], n: [f64; 2], c: f64, neighbour: usize) -> CellPolygon {
+  let side = |p: [f64; 2]| p[0]*n[0] + p[1]*n[1] - c;
+  let mut out = Vec::with_capacity(poly.len() + 1);
+  for ii in 0..poly.len() {
+    let (cur, next) = (poly[ii], poly[(ii + 1) % poly.len()]);
+    let (fc, fn_) = (side(cur.pos), side(next.pos));
+    let crossing = || {
+      let t = fc / (fc - fn_);
+      [cur.pos[0] + (next.pos[0] - cur.pos[0])*t, cur.pos[1] + (next.pos[1] - cur.pos[1])*t]
+    };
+    if fc <= 0.0 {
+      out.push(cur);
+      // Leaving the half-plane - the side from here follows the clip line
+      if fn_ > 0.0 { out.push(Corner { pos: crossing(), neighbour: Some(neighbour) }); }
+    }
+    else if fn_ <= 0.0 {
+      // Entering the half-plane - the side from here is the rest of this side
+      out.push(Corner { pos: crossing(), neighbour: cur.neighbour });
+    }
+  }
+  remove_short_sides(out)
+}
+
+/// Clips a polygon to the part closer to site `a` than site `b`.
+/// # Params
+/// * `poly` - The polygon to clip
+/// * `a`, `b` - The positions of the sites
+/// * `offset` - Added to the squared distance to `a` when comparing. This is
+///   the difference in weights for power diagrams, and 0 otherwise.
+/// * `neighbour` - The index of site `b`
+pub fn clip_bisector(poly: &[Corner], a: [f64; 2], b: [f64; 2], offset: f64, neighbour: usize) -> CellPolygon {
+  // |x - a|^2 + offset <= |x - b|^2  <=>  2x.(b - a) <= |b|^2 - |a|^2 - offset
+  let n = [2.0 * (b[0] - a[0]), 2.0 * (b[1] - a[1])];
+  let c = b[0]*b[0] + b[1]*b[1] - a[0]*a[0] - a[1]*a[1] - offset;
+  clip(poly, n, c, neighbour)
+}
+
+/// Removes corners which are (almost) at the same position as the corner
+/// before them, keeping the label of the later corner's side.
+fn remove_short_sides(mut poly: CellPolygon) -> CellPolygon {
+  let scale = poly.iter().fold(1.0f64, |m, c| m.max(c.pos[0].abs()).max(c.pos[1].abs()));
+  let eps = scale * 1e-9;
+  let same = |a: [f64; 2], b: [f64; 2]| (a[0] - b[0]).abs() <= eps && (a[1] - b[1]).abs() <= eps;
+  let mut ii = 0;
+  while ii < poly.len() && poly.len() > 1 {
+    let next = (ii + 1) % poly.len();
+    if same(poly[ii].pos, poly[next].pos) {
+      // The side from ii is 0 length - drop ii and let next's side start here
+      poly.remove(ii);
+    }
+    else { ii += 1; }
+  }
+  if poly.len() < 3 { poly.clear(); }
+  poly
+}
+
+/// Assembles cell polygons into a bounded `VoronoiDiagram`. Corners of
+/// neighbouring cells at the same position (within rounding error) become a
+/// single shared vertex.
+/// # Params
+/// * `sites` - The sites of the diagram
+/// * `bounds` - The rectangle the cells are clipped to - X, Y, W, H
+/// * `polygons` - The polygon of each site. Empty for sites with no cell.
+pub fn assemble(sites: &[[f32; 2]], bounds: &[f32; 4], polygons: &[CellPolygon]) -> VoronoiDiagram {
+  let eps = (bounds[2].abs() + bounds[3].abs()).max(1.0) as f64 * 1e-9;
+  let mut vertices : Vec<[f64; 2]> = Vec::new();
+  // Vertex indices by position, quantized to a grid of eps sized buckets
+  let mut grid : HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+  let mut find_vertex = |p: [f64; 2]| -> usize {
+    let key = ((p[0] / eps).floor() as i64, (p[1] / eps).floor() as i64);
+    for dx in -1..2 {
+      for dy in -1..2 {
+        if let Some(vs) = grid.get(&(key.0 + dx, key.1 + dy)) {
+          for &v in vs {
+            if (vertices[v][0] - p[0]).abs() <= eps && (vertices[v][1] - p[1]).abs() <= eps { return v; }
+          }
+        }
+      }
+    }
+    vertices.push(p);
+    grid.entry(key).or_default().push(vertices.len() - 1);
+    vertices.len() - 1
+  };
+
+  let mut cells : Vec<Cell> = (0..sites.len())
+    .map(|ii| Cell { site: ii, edges: Vec::new(), vertices: Vec::new() }).collect();
+  let mut edges = Vec::new();
+  let mut edge_of_pair : HashMap<(usize, usize), usize> = HashMap::new();
+  for (ii, poly) in polygons.iter().enumerate() {
+    let ids : Vec<usize> = poly.iter().map(|c| find_vertex(c.pos)).collect();
+    for jj in 0..poly.len() {
+      let (a, b) = (ids[jj], ids[(jj + 1) % ids.len()]);
+      if cells[ii].vertices.last() != Some(&a) { cells[ii].vertices.push(a); }
+      let other = match poly[jj].neighbour { Some(o) if a != b => o, _ => continue };
+      let key = (ii.min(other), ii.max(other));
+      let e = *edge_of_pair.entry(key).or_insert_with(|| {
+        // The cell is counter-clockwise, so this cell's site is on the left
+        edges.push(Edge { sites: [ii, other], vertices: [Some(a), Some(b)] });
+        edges.len() - 1
+      });
+      if !cells[ii].edges.contains(&e) { cells[ii].edges.push(e); }
+      if !cells[other].edges.contains(&e) { cells[other].edges.push(e); }
+    }
+    let c = &mut cells[ii];
+    if c.vertices.len() > 1 && c.vertices.first() == c.vertices.last() { c.vertices.pop(); }
+  }
+
+  // The dual triangles are at the vertices shared by 3 or more cells, away
+  // from the bounds. Fan triangulate the sites around each one.
+  let mut vertex_cells = vec![Vec::new(); vertices.len()];
+  for c in &cells {
+    for &v in &c.vertices { vertex_cells[v].push(c.site); }
+  }
+  let (min, max) = ([bounds[0] as f64, bounds[1] as f64], [(bounds[0] + bounds[2]) as f64, (bounds[1] + bounds[3]) as f64]);
+  let mut triangles = Vec::new();
+  for (v, around) in vertex_cells.iter_mut().enumerate() {
+    let p = vertices[v];
+    let on_bounds = (p[0] - min[0]).abs() <= eps || (p[0] - max[0]).abs() <= eps ||
+                    (p[1] - min[1]).abs() <= eps || (p[1] - max[1]).abs() <= eps;
+    if around.len() < 3 || on_bounds { continue; }
+    let angle = |s: usize| (sites[s][1] as f64 - p[1]).atan2(sites[s][0] as f64 - p[0]);
+    around.sort_by(|&a, &b| angle(a).partial_cmp(&angle(b)).unwrap_or(Ordering::Equal));
+    for jj in 1..around.len() - 1 {
+      triangles.push([around[0], around[jj], around[jj + 1]]);
+    }
+  }
+
+  let delaunay = Delaunay::new(sites.len(), triangles, &edges);
+  VoronoiDiagram {
+    sites: sites.to_vec(),
+    vertices: vertices.iter().map(|v| [v[0] as f32, v[1] as f32]).collect(),
+    edges,
+    cells,
+    bounds: Some(*bounds),
+    weights: None,
+    delaunay,
+  }
+}
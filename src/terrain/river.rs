@@ -0,0 +1,189 @@
+//! Drainage and rivers, flowing along the corners of terrain regions.
+//!
+//! Each corner (mesh vertex) gets the average elevation of the regions around
+//! it. Corners on the edge of the map or touching the ocean are outlets.
+//! Water flows from each corner to a neighbouring corner, always ending up at
+//! an outlet:
+//! 1. Flood inwards from the outlets, lowest corner first (priority-flood).
+//!    Each corner drains to the corner it was reached from. A corner lower
+//!    than the corner it was reached from is in a pit - water pools there,
+//!    forming a lake, until it overflows at the pit's lowest rim.
+//! 2. Every land corner gets one unit of rain. Flow is accumulated downhill,
+//!    so a corner's flow is the number of corners draining through it.
+//! 3. Corners with enough flow form rivers, traced from their sources down to
+//!    the ocean, a lake, or a larger river.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use cgmath::Vector2;
+use renderer::RendererController;
+use terrain::map::{CellKind, TerrainCell};
+use terrain::mesh::Mesh;
+
+/// The drainage network of a map. Indexed by corner, the same as the mesh's
+/// vertices.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Drainage {
+  /// The elevation of each corner - the average of the regions around it
+  pub elevation: Vec<f32>,
+  /// The elevation of the water surface at each corner. The same as
+  /// `elevation`, except in lakes.
+  pub water_level: Vec<f32>,
+  /// The corner each corner drains to, or `None` for outlets
+  pub downstream: Vec<Option<usize>>,
+  /// The amount of water flowing through each corner
+  pub flow: Vec<f32>,
+  /// Whether each corner is underwater, in a lake formed where water pools
+  pub lake: Vec<bool>,
+  /// Whether the water flowing out of each corner to its downstream corner is
+  /// a river
+  pub river: Vec<bool>,
+  /// The rivers, largest first
+  pub rivers: Vec<River>,
+}
+
+/// A river - a path of corners running downhill.
+#[derive(Clone, Debug, PartialEq)]
+pub struct River {
+  /// The corners of the river, from its source to where it ends. The last
+  /// corner is in the ocean, a lake, or another river.
+  pub corners: Vec<usize>,
+}
+
+/// A corner waiting to be flooded, ordered so a `BinaryHeap` pops the lowest
+/// first.
+#[derive(Clone, Copy, Debug)]
+struct FloodCorner {
+  level: f32,
+  corner: usize,
+}
+
+impl PartialEq for FloodCorner {
+  fn eq(&self, other: &FloodCorner) -> bool { self.cmp(other) == Ordering::Equal }
+}
+impl Eq for FloodCorner {}
+impl PartialOrd for FloodCorner {
+  fn partial_cmp(&self, other: &FloodCorner) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for FloodCorner {
+  fn cmp(&self, other: &FloodCorner) -> Ordering {
+    other.level.partial_cmp(&self.level).unwrap_or(Ordering::Equal)
+      .then(other.corner.cmp(&self.corner))
+  }
+}
+
+impl Drainage {
+  /// Computes the drainage of a map.
+  /// # Params
+  /// * `mesh` - The regions of the map
+  /// * `cells` - The attributes of each region
+  /// * `river_flow` - The flow a corner needs to be part of a river
+  pub fn new(mesh: &Mesh, cells: &[TerrainCell], river_flow: f32) -> Drainage {
+    let n = mesh.vertices.len();
+    let elevation : Vec<f32> = (0..n).map(|v| {
+      let (sum, count) = mesh.vertex_faces(v).fold((0.0, 0), |(s, c), f| (s + cells[f].elevation, c + 1));
+      if count > 0 { sum / count as f32 } else { 0.0 }
+    }).collect();
+
+    // Priority-flood from the outlets
+    let mut water_level = elevation.clone();
+    let mut downstream = vec![None; n];
+    let mut lake = vec![false; n];
+    let mut visited = vec![false; n];
+    let mut order = Vec::with_capacity(n);
+    let mut queue = BinaryHeap::new();
+    for v in 0..n {
+      let outlet = mesh.vertex_half_edges(v).any(|h| {
+        mesh.half_edges[h].twin.is_none() || cells[mesh.half_edges[h].face].kind == CellKind::Ocean
+      });
+      if outlet {
+        visited[v] = true;
+        queue.push(FloodCorner { level: elevation[v], corner: v });
+      }
+    }
+    while let Some(FloodCorner { level, corner }) = queue.pop() {
+      order.push(corner);
+      for h in mesh.vertex_half_edges(corner) {
+        let next = mesh.dest(h);
+        if visited[next] { continue; }
+        visited[next] = true;
+        downstream[next] = Some(corner);
+        if elevation[next] < level {
+          lake[next] = true;
+          water_level[next] = level;
+        }
+        queue.push(FloodCorner { level: water_level[next], corner: next });
+      }
+    }
+
+    // Accumulate flow, from the highest corners down
+    let mut flow = vec![0.0; n];
+    for &v in order.iter().rev() {
+      let is_land = mesh.vertex_faces(v).all(|f| cells[f].kind != CellKind::Ocean);
+      if is_land { flow[v] += 1.0; }
+      if let Some(d) = downstream[v] { flow[d] += flow[v]; }
+    }
+
+    let river = (0..n).map(|v| flow[v] >= river_flow && !lake[v] && downstream[v].is_some()).collect();
+    let mut drainage = Drainage { elevation, water_level, downstream, flow, lake, river, rivers: Vec::new() };
+    drainage.rivers = drainage.trace_rivers();
+    drainage
+  }
+
+  /// Splits the river corners into rivers. Where rivers join, the one with
+  /// the most flow carries on.
+  fn trace_rivers(&self) -> Vec<River> {
+    let n = self.flow.len();
+    // The upstream river corner with the most flow into each corner
+    let mut main_upstream : Vec<Option<usize>> = vec![None; n];
+    for c in (0..n).filter(|&c| self.river[c]) {
+      let d = self.downstream[c].unwrap();
+      let better = match main_upstream[d] {
+        Some(u) => self.flow[c] > self.flow[u],
+        None => true,
+      };
+      if better { main_upstream[d] = Some(c); }
+    }
+
+    // Rivers start at river corners with no river flowing into them, and
+    // carry on through each corner they're the main upstream of
+    let mut rivers : Vec<River> = (0..n).filter(|&c| self.river[c] && main_upstream[c].is_none())
+      .map(|source| {
+        let mut corners = vec![source];
+        let mut c = source;
+        while let Some(d) = self.downstream[c] {
+          corners.push(d);
+          if !self.river[d] || main_upstream[d] != Some(c) { break; }
+          c = d;
+        }
+        River { corners }
+      }).collect();
+    let end_flow = |r: &River| self.flow[r.corners[r.corners.len() - 2]];
+    rivers.sort_by(|a, b| end_flow(b).partial_cmp(&end_flow(a)).unwrap_or(Ordering::Equal));
+    rivers
+  }
+
+  /// Gets the flow of the river running between 2 neighbouring corners, or 0
+  /// if no river runs along that edge.
+  pub fn river_flow_between(&self, a: usize, b: usize) -> f32 {
+    let from = if self.downstream[a] == Some(b) { a } else if self.downstream[b] == Some(a) { b } else { return 0.0 };
+    if self.river[from] { self.flow[from] } else { 0.0 }
+  }
+}
+
+/// Draws the rivers of a map as lines, with width proportional to flow.
+/// # Params
+/// * `d` - The drainage of the map
+/// * `mesh` - The mesh the drainage was computed on
+/// * `width_per_flow` - The width of a river line per unit of flow
+/// * `r` - The renderer controller to send the geometry to
+pub fn draw_rivers(d: &Drainage, mesh: &Mesh, width_per_flow: f32, r: &RendererController) {
+  let col = [0.2, 0.4, 0.8, 1.0];
+  for river in &d.rivers {
+    for w in river.corners.windows(2) {
+      let (p0, p1) = (mesh.vertices[w[0]].pos, mesh.vertices[w[1]].pos);
+      let width = d.flow[w[0]] * width_per_flow;
+      r.line(Vector2::new(p0[0], p0[1]), Vector2::new(p1[0], p1[1]), width, col);
+    }
+  }
+}
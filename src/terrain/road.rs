@@ -0,0 +1,328 @@
+//! Road networks between settlements, following the borders of regions.
+//!
+//! Roads run along the edges between regions (the corners and edges of the
+//! mesh), from a settlement's site out to the corners of its region. Each
+//! edge costs its length, scaled by how hard the biomes either side are to
+//! build through, plus a cost for the change in elevation along it. Edges
+//! with water on both sides can't be built on, so roads go around lakes.
+//!
+//! Settlements are joined by a minimum spanning tree of the cheapest paths
+//! between them, found with Dijkstra's algorithm.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use cgmath::Vector2;
+use rand::Rng;
+use renderer::RendererController;
+use terrain::map::{Biome, CellKind, TerrainMap};
+use terrain::seeded_rng;
+
+/// The costs used when finding paths for roads.
+#[derive(Clone, Debug)]
+pub struct RoadCosts {
+  /// The cost of climbing or descending from elevation 0 to 1, in the same
+  /// units as distance
+  pub slope: f32,
+  /// The extra cost of running alongside a river, per unit of distance
+  pub river: f32,
+}
+
+impl Default for RoadCosts {
+  fn default() -> RoadCosts {
+    RoadCosts { slope: 400.0, river: 0.5 }
+  }
+}
+
+/// How hard a biome is to build a road through, as a multiplier of distance.
+/// `None` for water.
+pub fn biome_cost(biome: Biome) -> Option<f32> {
+  match biome {
+    Biome::Ocean | Biome::Lake => None,
+    Biome::Beach | Biome::Grassland | Biome::TemperateDesert | Biome::SubtropicalDesert => Some(1.0),
+    Biome::Shrubland | Biome::Bare | Biome::TropicalSeasonalForest => Some(1.5),
+    Biome::TemperateDeciduousForest | Biome::Scorched | Biome::Tundra => Some(2.0),
+    Biome::Taiga | Biome::TemperateRainForest | Biome::TropicalRainForest => Some(3.0),
+    Biome::Snow => Some(4.0),
+  }
+}
+
+/// A road between 2 settlements.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Road {
+  /// The regions of the settlements at each end
+  pub settlements: [usize; 2],
+  /// The corners the road passes through, from `settlements[0]` to
+  /// `settlements[1]`. The road also runs from each settlement's site to the
+  /// first and last corners.
+  pub corners: Vec<usize>,
+  /// The total cost of the road
+  pub cost: f32,
+}
+
+/// A network of roads connecting a set of settlements.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoadNetwork {
+  /// The regions containing settlements
+  pub settlements: Vec<usize>,
+  pub roads: Vec<Road>,
+  /// Every edge between 2 corners used by a road, without duplicates. The
+  /// lower corner index is first.
+  pub segments: Vec<[usize; 2]>,
+}
+
+/// A corner waiting to be visited by Dijkstra's algorithm, ordered so a
+/// `BinaryHeap` pops the cheapest first.
+#[derive(Clone, Copy, Debug)]
+struct Visit {
+  cost: f32,
+  corner: usize,
+}
+
+impl PartialEq for Visit {
+  fn eq(&self, other: &Visit) -> bool { self.cmp(other) == Ordering::Equal }
+}
+impl Eq for Visit {}
+impl PartialOrd for Visit {
+  fn partial_cmp(&self, other: &Visit) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for Visit {
+  fn cmp(&self, other: &Visit) -> Ordering {
+    other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+      .then(other.corner.cmp(&self.corner))
+  }
+}
+
+/// The cheapest paths from one settlement to every corner.
+struct Paths {
+  cost: Vec<f32>,
+  prev: Vec<Option<usize>>,
+}
+
+impl RoadNetwork {
+  /// Builds roads connecting a set of settlements.
+  /// # Params
+  /// * `map` - The map to build roads on
+  /// * `settlements` - The regions containing settlements. These should be
+  ///   land regions.
+  /// * `costs` - The costs used to find paths
+  /// # Returns
+  /// The road network. Settlements which can't be reached from each other,
+  /// e.g. on different islands, aren't connected.
+  pub fn generate(map: &TerrainMap, settlements: &[usize], costs: &RoadCosts) -> RoadNetwork {
+    let paths : Vec<Paths> = settlements.iter().map(|&s| shortest_paths(map, s, costs)).collect();
+    // The cost of the cheapest path between each pair of settlements, and the
+    // corner it enters the destination settlement's region from
+    let path_to = |from: usize, to: usize| -> Option<(f32, usize)> {
+      let region = settlements[to];
+      map.mesh.face_vertices(region).filter(|&c| paths[from].cost[c].is_finite())
+        .map(|c| (paths[from].cost[c] + spur_cost(map, region, c), c))
+        .fold(None, |best: Option<(f32, usize)>, p| if best.map_or(true, |b| p.0 < b.0) { Some(p) } else { best })
+    };
+
+    // Prim's algorithm over the complete graph of settlements
+    let n = settlements.len();
+    let mut in_tree = vec![false; n];
+    let mut best : Vec<Option<(f32, usize, usize)>> = vec![None; n];
+    let mut roads = Vec::new();
+    for start in 0..n {
+      if in_tree[start] { continue; }
+      // Start a new tree for each group of connected settlements
+      best[start] = Some((0.0, start, 0));
+      loop {
+        let next = (0..n).filter(|&ii| !in_tree[ii] && best[ii].is_some())
+          .fold(None, |m: Option<usize>, ii| if m.map_or(true, |m| best[ii].unwrap().0 < best[m].unwrap().0) { Some(ii) } else { m });
+        let next = match next { Some(ii) => ii, None => break };
+        in_tree[next] = true;
+        let (cost, from, entry) = best[next].unwrap();
+        if from != next {
+          // Walk back from the entry corner to the start settlement's region
+          let mut corners = vec![entry];
+          while let Some(p) = paths[from].prev[*corners.last().unwrap()] { corners.push(p); }
+          corners.reverse();
+          roads.push(Road { settlements: [settlements[from], settlements[next]], corners, cost });
+        }
+        for ii in (0..n).filter(|&ii| !in_tree[ii]) {
+          if let Some((cost, entry)) = path_to(next, ii) {
+            if best[ii].map_or(true, |b| cost < b.0) { best[ii] = Some((cost, next, entry)); }
+          }
+        }
+      }
+    }
+
+    let mut segments : Vec<[usize; 2]> = roads.iter()
+      .flat_map(|r| r.corners.windows(2).map(|w| [w[0].min(w[1]), w[0].max(w[1])]))
+      .collect();
+    segments.sort();
+    segments.dedup();
+    RoadNetwork { settlements: settlements.to_vec(), roads, segments }
+  }
+}
+
+/// The cost of the road from a region's site to one of its corners.
+fn spur_cost(map: &TerrainMap, region: usize, corner: usize) -> f32 {
+  let (s, c) = (map.diagram.sites[region], map.mesh.vertices[corner].pos);
+  let len = ((s[0] - c[0]).powi(2) + (s[1] - c[1]).powi(2)).sqrt();
+  len * biome_cost(map.cells[region].biome).unwrap_or(1.0)
+}
+
+/// The cost of a road along a half-edge, or `None` if it can't be built on.
+fn edge_cost(map: &TerrainMap, half_edge: usize, costs: &RoadCosts) -> Option<f32> {
+  let mesh = &map.mesh;
+  let h = &mesh.half_edges[half_edge];
+  // Don't build along the edge of the map
+  let twin = h.twin?;
+  let faces = [h.face, mesh.half_edges[twin].face];
+  let land : Vec<f32> = faces.iter().filter_map(|&f| biome_cost(map.cells[f].biome)).collect();
+  if land.is_empty() { return None; }
+  let biome = land.iter().sum::<f32>() / land.len() as f32;
+
+  let (a, b) = (h.origin, mesh.dest(half_edge));
+  let (pa, pb) = (mesh.vertices[a].pos, mesh.vertices[b].pos);
+  let len = ((pa[0] - pb[0]).powi(2) + (pa[1] - pb[1]).powi(2)).sqrt();
+  let climb = (map.drainage.elevation[a] - map.drainage.elevation[b]).abs();
+  let river = if map.drainage.river_flow_between(a, b) > 0.0 { costs.river } else { 0.0 };
+  Some(len * (biome + river) + climb * costs.slope)
+}
+
+/// Finds the cheapest paths from a settlement to every corner with
+/// Dijkstra's algorithm.
+fn shortest_paths(map: &TerrainMap, region: usize, costs: &RoadCosts) -> Paths {
+  let n = map.mesh.vertices.len();
+  let mut paths = Paths { cost: vec![f32::INFINITY; n], prev: vec![None; n] };
+  let mut queue = BinaryHeap::new();
+  for c in map.mesh.face_vertices(region) {
+    paths.cost[c] = spur_cost(map, region, c);
+    queue.push(Visit { cost: paths.cost[c], corner: c });
+  }
+  while let Some(Visit { cost, corner }) = queue.pop() {
+    if cost > paths.cost[corner] { continue; }
+    for h in map.mesh.vertex_half_edges(corner) {
+      let next = map.mesh.dest(h);
+      let edge = match edge_cost(map, h, costs) { Some(e) => e, None => continue };
+      if cost + edge < paths.cost[next] {
+        paths.cost[next] = cost + edge;
+        paths.prev[next] = Some(corner);
+        queue.push(Visit { cost: cost + edge, corner: next });
+      }
+    }
+  }
+  paths
+}
+
+/// Picks regions for settlements, spread over the land of a map. Settlements
+/// are never in water or next to each other.
+/// # Params
+/// * `map` - The map to place settlements on
+/// * `count` - The number of settlements to place. Fewer may be placed if
+///   there isn't enough land.
+/// * `seed` - The seed to pick regions with
+pub fn pick_settlements(map: &TerrainMap, count: usize, seed: u64) -> Vec<usize> {
+  let mut rng = seeded_rng(seed);
+  let mut land : Vec<usize> = (0..map.cells.len()).filter(|&ii| {
+    let kind = map.cells[ii].kind;
+    kind == CellKind::Land || kind == CellKind::Coast
+  }).collect();
+  rng.shuffle(&mut land);
+
+  let mut picked : Vec<usize> = Vec::with_capacity(count);
+  for region in land {
+    if picked.len() == count { break; }
+    if picked.contains(&region) || map.mesh.face_neighbours(region).any(|nb| picked.contains(&nb)) { continue; }
+    picked.push(region);
+  }
+  picked
+}
+
+/// Draws a road network for debugging. Settlements are drawn as white
+/// squares, and roads as brown lines.
+/// # Params
+/// * `net` - The road network to draw
+/// * `map` - The map the roads were built on
+/// * `r` - The renderer controller to send the geometry to
+pub fn draw_roads(net: &RoadNetwork, map: &TerrainMap, r: &RendererController) {
+  let col = [0.55, 0.4, 0.25, 1.0];
+  let line = |a: [f32; 2], b: [f32; 2]| r.line(Vector2::new(a[0], a[1]), Vector2::new(b[0], b[1]), 2.0, col);
+  for &[a, b] in &net.segments {
+    line(map.mesh.vertices[a].pos, map.mesh.vertices[b].pos);
+  }
+  for road in &net.roads {
+    let (first, last) = (road.corners[0], road.corners[road.corners.len() - 1]);
+    line(map.diagram.sites[road.settlements[0]], map.mesh.vertices[first].pos);
+    line(map.mesh.vertices[last].pos, map.diagram.sites[road.settlements[1]]);
+  }
+  for &s in &net.settlements {
+    let p = map.diagram.sites[s];
+    r.rect(&[p[0] - 3.0, p[1] - 3.0, 6.0, 6.0], &[1.0, 1.0, 1.0, 1.0]);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use terrain::map::MapConfig;
+
+  fn generate(seed: u64) -> (TerrainMap, RoadNetwork) {
+    let map = TerrainMap::generate(&MapConfig { num_regions: 400, .. Default::default() }, seed);
+    let roads = RoadNetwork::generate(&map, &pick_settlements(&map, 10, seed), &Default::default());
+    (map, roads)
+  }
+
+  /// Finds the half-edge from corner `a` to corner `b`.
+  fn half_edge(map: &TerrainMap, a: usize, b: usize) -> Option<usize> {
+    map.mesh.vertex_half_edges(a).find(|&h| map.mesh.dest(h) == b)
+  }
+
+  #[test]
+  fn roads_follow_land_edges() {
+    for seed in 0..4 {
+      let (map, net) = generate(seed);
+      assert!(!net.roads.is_empty());
+      for road in &net.roads {
+        assert!(map.mesh.face_vertices(road.settlements[0]).any(|c| c == road.corners[0]));
+        assert!(map.mesh.face_vertices(road.settlements[1]).any(|c| c == *road.corners.last().unwrap()));
+        for w in road.corners.windows(2) {
+          let h = half_edge(&map, w[0], w[1]).expect("road jumps between corners which aren't joined");
+          assert!(edge_cost(&map, h, &Default::default()).is_some(), "road runs along water or the map's edge");
+          let twin = map.mesh.half_edges[h].twin.unwrap();
+          let faces = [map.mesh.half_edges[h].face, map.mesh.half_edges[twin].face];
+          assert!(faces.iter().any(|&f| biome_cost(map.cells[f].biome).is_some()), "road runs between 2 water regions");
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn roads_connect_every_settlement() {
+    for seed in 0..4 {
+      let (map, net) = generate(seed);
+      let n = net.settlements.len();
+      // Group the settlements joined by roads
+      let mut group : Vec<usize> = (0..n).collect();
+      for road in &net.roads {
+        let ends : Vec<usize> = road.settlements.iter()
+          .map(|s| net.settlements.iter().position(|x| x == s).unwrap()).collect();
+        let (from, to) = (group[ends[0]], group[ends[1]]);
+        for g in &mut group { if *g == from { *g = to; } }
+      }
+      // Every pair of settlements with a path between them must be joined
+      for a in 0..n {
+        let paths = shortest_paths(&map, net.settlements[a], &Default::default());
+        for b in 0..n {
+          let reachable = map.mesh.face_vertices(net.settlements[b]).any(|c| paths.cost[c].is_finite());
+          assert_eq!(reachable, group[a] == group[b], "settlements {} and {}", a, b);
+        }
+      }
+      // A spanning tree has 1 road fewer than settlements in each group
+      let mut groups = group.clone();
+      groups.sort();
+      groups.dedup();
+      assert_eq!(net.roads.len(), n - groups.len());
+    }
+  }
+
+  #[test]
+  fn same_seed_same_roads() {
+    assert_eq!(generate(3), generate(3));
+    assert!(generate(3).1 != generate(4).1);
+  }
+}
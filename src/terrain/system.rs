@@ -0,0 +1,51 @@
+use specs;
+use component::*;
+use state::{GlobalState, Stage};
+use renderer::{Renderer, RendererController};
+use terrain::diagram::TerrainDiagram;
+use terrain::locate::SiteIndex;
+
+/// The ECS system which finds the region each entity is in. Requires a
+/// `SiteIndex` resource in the world, and updates the `CompRegion` of every
+/// entity with a `CompAABB`.
+#[derive(Clone)]
+pub struct SysLocateRegion;
+
+impl specs::System<GlobalState> for SysLocateRegion {
+  fn run(&mut self, arg: specs::RunArg, g: GlobalState) {
+    if g.stage != Stage::Frame { arg.fetch(|_| ()); return; }
+    let (index, all_aabb, mut all_region) = arg.fetch(|w| {
+      (w.read_resource::<SiteIndex>(), w.read::<CompAABB>(), w.write::<CompRegion>())
+    });
+
+    use specs::Join;
+    for (aabb, region) in (&all_aabb, &mut all_region).join() {
+      let center = [aabb.0[0] + aabb.0[2] / 2.0, aabb.0[1] + aabb.0[3] / 2.0];
+      region.0 = index.cell_at(center);
+    }
+  }
+}
+
+/// The ECS system which keeps the `TerrainDiagram` resource up to date,
+/// rebuilding the diagram only when its sites have changed, and sends its
+/// cached geometry to the renderer.
+#[derive(Clone)]
+pub struct SysTerrain {
+  r_controller: RendererController,
+}
+
+impl SysTerrain {
+  /// Create a new terrain system, which will draw to the given renderer.
+  pub fn new(r: &Renderer) -> SysTerrain {
+    SysTerrain { r_controller: r.get_renderer_controller() }
+  }
+}
+
+impl specs::System<GlobalState> for SysTerrain {
+  fn run(&mut self, arg: specs::RunArg, g: GlobalState) {
+    if g.stage != Stage::Frame { arg.fetch(|_| ()); return; }
+    let mut terrain = arg.fetch(|w| w.write_resource::<TerrainDiagram>());
+    terrain.update();
+    terrain.draw(&self.r_controller);
+  }
+}
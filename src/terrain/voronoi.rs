@@ -1,241 +1,1049 @@
+//! Voronoi diagram generation using Fortune's algorithm.
+//!
+//! `voronoi()` is a pure function which returns a `VoronoiDiagram`, so the
+//! result can be used by any part of the game. `voronoi_bounded()` does the
+//! same, but clips the diagram to a rectangle so every cell is a closed
+//! polygon. `voronoi_weighted()` computes a clipped power diagram, where each
+//! site has a weight controlling the size of its cell. The Delaunay
+//! triangulation of the sites is computed at the same time, and stored in
+//! `VoronoiDiagram::delaunay`. `draw_voronoi()` takes a computed diagram and
+//! sends debug geometry to the renderer.
+
 use renderer::{RendererController};
-use std::collections::VecDeque;
+use terrain::delaunay::Delaunay;
+use terrain::locate::SiteIndex;
+use terrain::polygon::{self, CellPolygon};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use cgmath::Vector2;
 
-/// Struct representing a vertex in the graph
-#[derive(Clone, Debug)]
-struct Node {
-  /// A list of neighbours to this node. They are represented as an index into
-  /// a list of nodes.
-  neighbour_list: Vec<usize>,
-  pos: [f32; 2],
+/// A Voronoi diagram computed from a list of sites.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoronoiDiagram {
+  /// The input sites, in the order they were given.
+  pub sites: Vec<[f32; 2]>,
+  /// The Voronoi vertices (the points equidistant from 3 or more sites).
+  pub vertices: Vec<[f32; 2]>,
+  /// The edges of the diagram. Each edge separates exactly 2 sites.
+  pub edges: Vec<Edge>,
+  /// One cell per site. `cells[i]` is the cell of `sites[i]`.
+  pub cells: Vec<Cell>,
+  /// The rectangle the diagram was clipped to, if any - X, Y, W, H format.
+  pub bounds: Option<[f32; 4]>,
+  /// The weight of each site, for power diagrams from `voronoi_weighted()`.
+  /// `None` for ordinary Voronoi diagrams.
+  pub weights: Option<Vec<f32>>,
+  /// The Delaunay triangulation of the sites. This is the dual of the
+  /// unclipped diagram, so for bounded diagrams it also includes triangles
+  /// whose Voronoi vertex is outside the bounds.
+  pub delaunay: Delaunay,
+}
+
+/// An edge in a Voronoi diagram, separating the cells of 2 sites.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+  /// Indices into `VoronoiDiagram::sites` of the 2 sites this edge separates.
+  pub sites: [usize; 2],
+  /// Indices into `VoronoiDiagram::vertices` of the 2 ends of this edge. An
+  /// end is `None` if the edge runs off to infinity in that direction.
+  pub vertices: [Option<usize>; 2],
+}
+
+/// The cell of a single site - the region of the plane closer to this site
+/// than to any other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cell {
+  /// Index into `VoronoiDiagram::sites` of the site this cell belongs to.
+  pub site: usize,
+  /// Indices into `VoronoiDiagram::edges` of the edges bordering this cell.
+  pub edges: Vec<usize>,
+  /// Indices into `VoronoiDiagram::vertices` of the corners of this cell, as
+  /// a closed, counter-clockwise (positive signed area) convex polygon. Note
+  /// that with the renderer's Y-down projection this appears clockwise on
+  /// screen.
+  ///
+  /// Only bounded diagrams have polygons - this is empty for diagrams from
+  /// `voronoi()`, as well as for cells which lie entirely outside the bounds.
+  pub vertices: Vec<usize>,
+}
+
+impl VoronoiDiagram {
+  /// Gets the corners of a cell's polygon, counter-clockwise. Empty for
+  /// unbounded diagrams.
+  pub fn cell_polygon(&self, cell: usize) -> Vec<[f32; 2]> {
+    self.cells[cell].vertices.iter().map(|&v| self.vertices[v]).collect()
+  }
+
+  /// Calculates the area of a cell's polygon. 0 for unbounded diagrams.
+  pub fn cell_area(&self, cell: usize) -> f32 {
+    polygon_area_centroid(&self.cell_polygon(cell)).0 as f32
+  }
+
+  /// Calculates the centroid (center of mass) of a cell's polygon.
+  /// # Returns
+  /// The centroid, or `None` if the cell has no polygon or no area.
+  pub fn cell_centroid(&self, cell: usize) -> Option<[f32; 2]> {
+    let (area, c) = polygon_area_centroid(&self.cell_polygon(cell));
+    if area > 0.0 { Some([c[0] as f32, c[1] as f32]) } else { None }
+  }
+}
+
+/// Calculates the signed area and centroid of a polygon with the shoelace
+/// formula. The centroid is meaningless if the area is 0.
+fn polygon_area_centroid(poly: &[[f32; 2]]) -> (f64, [f64; 2]) {
+  if poly.is_empty() { return (0.0, [0.0; 2]); }
+  // Work relative to the first point to keep precision
+  let o = [poly[0][0] as f64, poly[0][1] as f64];
+  let (mut area2, mut cx, mut cy) = (0.0, 0.0, 0.0);
+  for ii in 0..poly.len() {
+    let (a, b) = (poly[ii], poly[(ii + 1) % poly.len()]);
+    let (ax, ay) = (a[0] as f64 - o[0], a[1] as f64 - o[1]);
+    let (bx, by) = (b[0] as f64 - o[0], b[1] as f64 - o[1]);
+    let cross = ax*by - bx*ay;
+    area2 += cross;
+    cx += (ax + bx) * cross;
+    cy += (ay + by) * cross;
+  }
+  if area2 == 0.0 { return (0.0, o); }
+  (area2 / 2.0, [o[0] + cx / (3.0 * area2), o[1] + cy / (3.0 * area2)])
 }
 
 /// An event in fortune's algorithm
-enum Event {
+#[derive(Clone, Copy, Debug)]
+enum EventKind {
   /// A site event.
   /// # Parameters
-  /// * Position of event
-  Site([f32; 2]),
+  /// * Index of the site
+  Site(usize),
 
   /// A circle event.
   /// # Parameters
-  /// * Position of event
-  /// * Radius of circle
-  /// * ID of the arc
-  Circle([f32; 2], f32, u32),
+  /// * Index of the arc which will be removed by this event
+  /// * ID of the event, used to check if the event is still valid
+  /// * Center of the circle
+  Circle(usize, usize, [f64; 2]),
+}
+
+/// An event in the event queue, along with the position of the sweep line when
+/// the event happens.
+#[derive(Clone, Copy, Debug)]
+struct Event {
+  /// The Y position of the sweep line when this event happens
+  y: f64,
+  /// The X position of the event, used to order events with the same Y
+  x: f64,
+  kind: EventKind,
 }
 
-type EventQueue = VecDeque<Event>;
+impl Event {
+  /// Whether this is a site event. Used to order site events after circle
+  /// events happening at the same point.
+  fn is_site(&self) -> bool {
+    match self.kind { EventKind::Site(_) => true, EventKind::Circle(..) => false }
+  }
+}
+
+impl PartialEq for Event {
+  fn eq(&self, other: &Event) -> bool { self.cmp(other) == Ordering::Equal }
+}
+impl Eq for Event {}
+impl PartialOrd for Event {
+  fn partial_cmp(&self, other: &Event) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for Event {
+  /// Events are ordered in reverse, so that a `BinaryHeap` (a max heap) pops
+  /// the event with the lowest Y first, then the lowest X, then circle events
+  /// before site events. Site events at the same point are popped in order of
+  /// site index, so the first of a set of duplicate sites gets the cell.
+  fn cmp(&self, other: &Event) -> Ordering {
+    other.y.partial_cmp(&self.y).unwrap_or(Ordering::Equal)
+      .then(other.x.partial_cmp(&self.x).unwrap_or(Ordering::Equal))
+      .then(other.is_site().cmp(&self.is_site()))
+      .then_with(|| match (self.kind, other.kind) {
+        (EventKind::Site(a), EventKind::Site(b)) => b.cmp(&a),
+        _ => Ordering::Equal,
+      })
+  }
+}
 
 /// Arc struct, represents the parabola made up of the points equidistant from
-/// a site and the scan line. 
+/// a site and the sweep line. Arcs are nodes of the beach line tree.
 #[derive(Clone, Debug)]
 struct Arc {
-  /// A unique ID for this arc, used to identify it
-  id: u32,
-  /// The coordinates of the point this is formed from
-  p: [f32; 2],
-  /// The vertex that the left side of this arc is drawing an edge from, if any
-  l_vert : Option<usize>,
-  /// The vertex that the right side of this arc is drawing an edge from, if any
-  r_vert : Option<usize>,
+  /// The index of the site this arc is formed from
+  site: usize,
+  /// Parent node in the beach line tree
+  parent: Option<usize>,
+  /// Left child in the beach line tree
+  left: Option<usize>,
+  /// Right child in the beach line tree
+  right: Option<usize>,
+  /// The arc to the left of this one on the beach line
+  prev: Option<usize>,
+  /// The arc to the right of this one on the beach line
+  next: Option<usize>,
+  /// Random heap priority, used to keep the tree balanced
+  priority: u32,
+  /// The ID of the circle event which will remove this arc, if any
+  event: Option<usize>,
+  /// The edge being traced by the breakpoint between this arc and the next
+  r_edge: Option<usize>,
 }
 
-pub fn voronoi(points: &Vec<[f32; 2]>, r: RendererController) {
-  // Create event queue
-  let mut ev_queue = EventQueue::new();
+/// The beach line - a treap of arcs, ordered left to right. Arcs are also
+/// linked to their neighbours on the beach line, so finding the arcs either
+/// side of an arc is O(1).
+struct BeachLine {
+  /// Arena of arcs. Removed arcs are put on the free list to be reused.
+  arcs: Vec<Arc>,
+  /// List of unused indices into `arcs`
+  free: Vec<usize>,
+  /// The root of the tree
+  root: Option<usize>,
+  /// State of the xorshift generator used to generate arc priorities
+  rng: u32,
+}
 
-  // Draw sites
-  for p in points { r.rect(&[p[0], p[1], 2.0, 2.0], &[1.0, 0.0, 0.0, 1.0]); }
+impl BeachLine {
+  fn new() -> BeachLine {
+    BeachLine { arcs: Vec::new(), free: Vec::new(), root: None, rng: 0x9E37_79B9 }
+  }
 
-  // Sort site events by height, add them in increasing y
-  {
-    let mut points_clone = points.clone();
-    let mut points_added = 0;
-    while points_added < points.len() {
-      let mut highest_ix = None; // Index of the highest site
-      for (ii, p) in points_clone.iter().enumerate() {
-        if highest_ix.is_none() || p[1] > (points_clone[highest_ix.unwrap()] as [f32; 2])[1] { 
-          highest_ix = Some(ii);
-        }
+  /// Creates a new arc which isn't yet part of the tree, returning its index.
+  fn alloc(&mut self, site: usize) -> usize {
+    // xorshift32
+    self.rng ^= self.rng << 13;
+    self.rng ^= self.rng >> 17;
+    self.rng ^= self.rng << 5;
+    let arc = Arc { site, parent: None, left: None, right: None, prev: None, next: None,
+                    priority: self.rng, event: None, r_edge: None };
+    match self.free.pop() {
+      Some(ix) => { self.arcs[ix] = arc; ix }
+      None => { self.arcs.push(arc); self.arcs.len() - 1 }
+    }
+  }
+
+  /// Finds the arc above a given x coordinate.
+  /// # Params
+  /// * `x` - The x coordinate to search for
+  /// * `l` - The Y position of the sweep line
+  /// * `sites` - The list of sites, used to calculate breakpoints
+  fn find(&self, x: f64, l: f64, sites: &[[f64; 2]]) -> usize {
+    let mut n = self.root.unwrap();
+    loop {
+      let arc = &self.arcs[n];
+      if let (Some(prev), Some(left)) = (arc.prev, arc.left) {
+        if x < breakpoint(sites[self.arcs[prev].site], sites[arc.site], l) { n = left; continue; }
+      }
+      if let (Some(next), Some(right)) = (arc.next, arc.right) {
+        if x > breakpoint(sites[arc.site], sites[self.arcs[next].site], l) { n = right; continue; }
+      }
+      return n;
+    }
+  }
+
+  /// Replaces the link from `n`'s parent to `n` with a link to `new`.
+  fn replace_child(&mut self, n: usize, new: Option<usize>) {
+    match self.arcs[n].parent {
+      Some(p) => {
+        if self.arcs[p].left == Some(n) { self.arcs[p].left = new; }
+        else { self.arcs[p].right = new; }
+      }
+      None => self.root = new,
+    }
+    if let Some(new) = new { self.arcs[new].parent = self.arcs[n].parent; }
+  }
+
+  /// Rotates `n` above its parent, keeping the in-order ordering of the tree.
+  fn rotate_up(&mut self, n: usize) {
+    let p = self.arcs[n].parent.unwrap();
+    self.replace_child(p, Some(n));
+    if self.arcs[p].left == Some(n) {
+      let moved = self.arcs[n].right;
+      self.arcs[p].left = moved;
+      if let Some(m) = moved { self.arcs[m].parent = Some(p); }
+      self.arcs[n].right = Some(p);
+    }
+    else {
+      let moved = self.arcs[n].left;
+      self.arcs[p].right = moved;
+      if let Some(m) = moved { self.arcs[m].parent = Some(p); }
+      self.arcs[n].left = Some(p);
+    }
+    self.arcs[p].parent = Some(n);
+  }
+
+  /// Inserts the first arc into an empty beach line.
+  fn insert_root(&mut self, n: usize) {
+    self.root = Some(n);
+  }
+
+  /// Inserts the arc `n` directly to the right of the arc `at`.
+  fn insert_after(&mut self, at: usize, n: usize) {
+    // Link into the list
+    let next = self.arcs[at].next;
+    self.arcs[n].prev = Some(at);
+    self.arcs[n].next = next;
+    self.arcs[at].next = Some(n);
+    if let Some(next) = next { self.arcs[next].prev = Some(n); }
+
+    // Link into the tree - either as the right child of `at`, or the left
+    // child of the leftmost node in `at`'s right subtree.
+    match self.arcs[at].right {
+      None => {
+        self.arcs[at].right = Some(n);
+        self.arcs[n].parent = Some(at);
       }
-      if highest_ix.is_none() { break; } // No points left to add
+      Some(mut s) => {
+        while let Some(l) = self.arcs[s].left { s = l; }
+        self.arcs[s].left = Some(n);
+        self.arcs[n].parent = Some(s);
+      }
+    }
+
+    // Rotate up until the heap property holds
+    while let Some(p) = self.arcs[n].parent {
+      if self.arcs[p].priority >= self.arcs[n].priority { break; }
+      self.rotate_up(n);
+    }
+  }
+
+  /// Removes the arc `n` from the beach line, freeing it for reuse.
+  fn remove(&mut self, n: usize) {
+    // Rotate the node down until it's a leaf
+    loop {
+      let child = match (self.arcs[n].left, self.arcs[n].right) {
+        (None, None) => break,
+        (Some(c), None) | (None, Some(c)) => c,
+        (Some(l), Some(r)) => if self.arcs[l].priority > self.arcs[r].priority { l } else { r },
+      };
+      self.rotate_up(child);
+    }
+    self.replace_child(n, None);
+
+    // Unlink from the list
+    let (prev, next) = (self.arcs[n].prev, self.arcs[n].next);
+    if let Some(prev) = prev { self.arcs[prev].next = next; }
+    if let Some(next) = next { self.arcs[next].prev = prev; }
+    self.free.push(n);
+  }
+}
+
+/// Calculates the X position of the breakpoint between the arc of site `a` on
+/// the left and the arc of site `b` on the right.
+/// # Params
+/// * `a` - The site of the left arc
+/// * `b` - The site of the right arc
+/// * `l` - The Y position of the sweep line
+fn breakpoint(a: [f64; 2], b: [f64; 2], l: f64) -> f64 {
+  // Sites on the same row are separated by a vertical line halfway between
+  // them. A site on the sweep line has a degenerate arc, which is a vertical
+  // ray up from the site.
+  if a[1] == b[1] { return (a[0] + b[0]) / 2.0; }
+  if a[1] == l { return a[0]; }
+  if b[1] == l { return b[0]; }
+
+  // The parabola of a site s is y = ((x - s.x)^2 + s.y^2 - l^2) / 2(s.y - l).
+  // Equating the parabolas of a and b gives a quadratic in x.
+  let da = 2.0 * (a[1] - l);
+  let db = 2.0 * (b[1] - l);
+  let qa = 1.0/da - 1.0/db;
+  let qb = 2.0 * (b[0]/db - a[0]/da);
+  let qc = a[0]*a[0]/da - b[0]*b[0]/db + (a[1] - b[1]) / 2.0;
+  let disc = (qb*qb - 4.0*qa*qc).max(0.0).sqrt();
+  let r1 = (-qb - disc) / (2.0 * qa);
+  let r2 = (-qb + disc) / (2.0 * qa);
+
+  // The site closer to the sweep line has the narrower parabola, which is
+  // only above the other parabola between the 2 roots.
+  if a[1] > b[1] { r1.max(r2) } else { r1.min(r2) }
+}
+
+/// Calculates the center of the circle passing through 3 points, or `None` if
+/// the points are collinear.
+fn circumcenter(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> Option<[f64; 2]> {
+  // Translate so a is at the origin to keep precision
+  let (bx, by) = (b[0] - a[0], b[1] - a[1]);
+  let (cx, cy) = (c[0] - a[0], c[1] - a[1]);
+  let d = 2.0 * (bx*cy - by*cx);
+  if d == 0.0 { return None; }
+  let (b2, c2) = (bx*bx + by*by, cx*cx + cy*cy);
+  Some([a[0] + (cy*b2 - by*c2) / d, a[1] + (bx*c2 - cx*b2) / d])
+}
+
+/// The state of Fortune's algorithm while the sweep line moves down the plane.
+struct Fortune<'a> {
+  /// The sites, as doubles to keep precision
+  sites: &'a [[f64; 2]],
+  /// Event queue, popping the next event first
+  queue: BinaryHeap<Event>,
+  /// Whether each circle event is still valid. Indexed by event ID.
+  event_valid: Vec<bool>,
+  beach: BeachLine,
+  vertices: Vec<[f64; 2]>,
+  edges: Vec<Edge>,
+  /// Delaunay triangles, one per circle event
+  triangles: Vec<[usize; 3]>,
+  /// The distance under which 2 vertices are considered the same vertex
+  eps: f64,
+}
+
+impl<'a> Fortune<'a> {
+  /// Sets the end of an edge traced by the breakpoint between the arcs of
+  /// `left_site` and its right neighbour.
+  ///
+  /// Edges are oriented so that `sites[0]` is on the left when walking from
+  /// `vertices[0]` to `vertices[1]`. With this orientation, a breakpoint whose
+  /// left arc belongs to `sites[0]` traces `vertices[1]`, and the other
+  /// breakpoint traces `vertices[0]`.
+  fn finish_edge(&mut self, edge: usize, left_site: usize, vert: usize) {
+    let end = if self.edges[edge].sites[0] == left_site { 1 } else { 0 };
+    self.edges[edge].vertices[end] = Some(vert);
+  }
 
-      let highest_ix = highest_ix.unwrap();
+  /// Cancels the circle event of an arc, if it has one.
+  fn cancel_event(&mut self, arc: usize) {
+    if let Some(ev) = self.beach.arcs[arc].event.take() { self.event_valid[ev] = false; }
+  }
+
+  /// Checks whether the given arc will be squeezed out of the beach line by
+  /// its neighbours, and if so adds a circle event for it.
+  fn check_circle_event(&mut self, arc: usize) {
+    let (prev, next) = match (self.beach.arcs[arc].prev, self.beach.arcs[arc].next) {
+      (Some(prev), Some(next)) => (prev, next),
+      _ => return,
+    };
+    let (a, b, c) = (self.beach.arcs[prev].site, self.beach.arcs[arc].site, self.beach.arcs[next].site);
+    if a == c { return; }
+    let (pa, pb, pc) = (self.sites[a], self.sites[b], self.sites[c]);
+
+    // The breakpoints either side of the arc only converge if a, b, c make a
+    // clockwise turn (with y pointing down the sweep direction).
+    let cross = (pb[0] - pa[0])*(pc[1] - pb[1]) - (pb[1] - pa[1])*(pc[0] - pb[0]);
+    if cross <= 0.0 { return; }
+    let center = match circumcenter(pa, pb, pc) { Some(c) => c, None => return };
+    let r = ((center[0] - pb[0]).powi(2) + (center[1] - pb[1]).powi(2)).sqrt();
+
+    let id = self.event_valid.len();
+    self.event_valid.push(true);
+    self.beach.arcs[arc].event = Some(id);
+    self.queue.push(Event { y: center[1] + r, x: center[0], kind: EventKind::Circle(arc, id, center) });
+  }
+
+  fn site_event(&mut self, site: usize) {
+    let p = self.sites[site];
+    let new = self.beach.alloc(site);
+    if self.beach.root.is_none() {
+      self.beach.insert_root(new);
+      return;
+    }
 
-      // Found highest, now add to event queue
-      ev_queue.push_back(Event::Site(points_clone[highest_ix].clone()));
+    let found = self.beach.find(p[0], p[1], self.sites);
+    let q = self.beach.arcs[found].site;
+    let edge = self.edges.len();
+    self.edges.push(Edge { sites: [q, site], vertices: [None, None] });
 
-      // Remove this point from the cloned list of points
-      points_clone.remove(highest_ix);
-      points_added += 1;
+    if self.sites[q][1] == p[1] {
+      // Degenerate case - all sites so far are on the same row as this one,
+      // so there's no arc above to split. Since sites on the same row are
+      // processed left to right, the new arc goes to the right of the last
+      // arc, and the edge between them is a vertical line.
+      self.beach.arcs[new].r_edge = self.beach.arcs[found].r_edge;
+      self.beach.arcs[found].r_edge = Some(edge);
+      self.beach.insert_after(found, new);
+      return;
     }
+
+    // Split the arc above into 2, with the new arc in between. Both
+    // breakpoints of the new arc trace the same edge in opposite directions.
+    self.cancel_event(found);
+    let dup = self.beach.alloc(q);
+    self.beach.arcs[dup].r_edge = self.beach.arcs[found].r_edge;
+    self.beach.arcs[found].r_edge = Some(edge);
+    self.beach.arcs[new].r_edge = Some(edge);
+    self.beach.insert_after(found, new);
+    self.beach.insert_after(new, dup);
+
+    self.check_circle_event(found);
+    self.check_circle_event(dup);
   }
 
-  // Process events in a loop
-  let mut curr_arc_id = 0;
-  let mut arc_list = Vec::new(); // A list of arcs processed
-  let mut node_list = Vec::new(); // A list of nodes (vertices)
-  while ev_queue.len() != 0 {
-    let e = ev_queue.pop_front();
-    match e.unwrap() {
-      // Process site event, add a new arc to the list
-      Event::Site(p) => {
-        // If no arcs, just push
-        if arc_list.is_empty() { 
-          arc_list.push(Arc{ p: p, l_vert: None, r_vert: None, id: curr_arc_id }); 
-          curr_arc_id += 1;
-          continue; 
-        }
+  fn circle_event(&mut self, arc: usize, center: [f64; 2]) {
+    let prev = self.beach.arcs[arc].prev.unwrap();
+    let next = self.beach.arcs[arc].next.unwrap();
+    self.cancel_event(prev);
+    self.cancel_event(next);
 
-        // Otherwise, find the arc that lowest on the vertical line where 
-        // x = p.x. Remember, sweep line is at p.y.
-        // Let p = The point of the site, a = the point making the arc.
-        // The equation of the parabola made from site A is:
-        // y = (p.y^2 + a.y^2 + (a.x - x)^2)/(2(a.y - p.y))
-        // The exact y value where the new site's parabola intersects this arc
-        // is obtained by substituting p.x for x.
-        let mut lowest_arc_ix = 0;
-        let mut lowest_height = 0.0;
-        for ii in 0..arc_list.len() {
-          // TODO Possible optimisation by discarding arcs based on half the y distance from a to p.
-          // Calculate intersection, then if it's the lowest, set lowest_arc.
-          let a = arc_list[ii].p;
-          let intersection_y = (p[1].powi(2) + a[1].powi(2) + (a[0] - p[0]).powi(2))/(2.0 * (a[1] - p[1]));
-          if lowest_height < intersection_y {
-            lowest_arc_ix = ii; 
-            lowest_height = intersection_y;
-          }
-        }
+    let l_edge = self.beach.arcs[prev].r_edge.unwrap();
+    let r_edge = self.beach.arcs[arc].r_edge.unwrap();
+    let (a, b, c) = (self.beach.arcs[prev].site, self.beach.arcs[arc].site, self.beach.arcs[next].site);
 
-        // Now we have the lowest arc, split it into 3 new arcs
-        let arc_2 = arc_list[lowest_arc_ix].clone();
-        let new_arc = Arc { p: p, l_vert: None, r_vert: None, id: curr_arc_id };
-        curr_arc_id += 1;
-
-        // Insert these new arcs back into the list
-        arc_list.insert(lowest_arc_ix+1, new_arc);
-        arc_list.insert(lowest_arc_ix+2, arc_2);
-
-        // Now check for circle events with the newly inserted site arc
-
-        /// A function to check whether a given point and its neighbours will
-        /// create a circle event. If so, returns the circle event.
-        /// # Params
-        /// * `arc_list` - The list of arcs
-        /// * `p_ix` - An index into the list pointing to the arc to be checked
-        /// * `scanline` - The Y position of the scanline (not the beach line)
-        fn check_circle_event(arc_list: &Vec<Arc>, p_ix: usize, scanline: f32) -> Option<Event>{
-          if p_ix == 0 || p_ix == arc_list.len() - 1 { return None; }
-          // We have neighbours, so compute the circumcircle center and radius
-          // Let a, b and c be triangle points
-          // Get perp bisectors of ab and bc, get their intersection. This is the center.
-          // Do a lot of rearranging and you get 2 huge equations to give you x, then y:
-          let (a, b, c) = (arc_list[p_ix-1].p, arc_list[p_ix].p, arc_list[p_ix+1].p);
-          let center_x = 
-            ((b[1].powi(2) + a[0].powi(2) - a[1].powi(2) - b[0].powi(2))/(2.0*(b[1]-a[1]))
-             - (c[1].powi(2) + b[0].powi(2) - b[1].powi(2) - c[0].powi(2))/(2.0*(c[1]-b[1])))
-            / ((c[0] - b[0])/(c[1] - b[1]) - (b[0] - a[0])/(b[1] - a[1]));
-          // Y = mx + c
-          let center_y = ((b[0] - a[0])/(b[1] - a[1]))*center_x + 
-            (b[1].powi(2) + a[0].powi(2) - a[1].powi(2) - b[0].powi(2))/(2.0*(b[1] - a[1]));
-
-          // Calculate r with pythagoras
-          let r = ((center_x - a[0]).powi(2) + (center_y - a[1]).powi(2)).sqrt();
-
-          // Check whether the bottom of the circle is below the scan line
-          return 
-            if center_y + r > scanline {
-              Some(Event::Circle([center_x, center_y], r, arc_list[p_ix].id ))
-            }
-            else { None }
+    // If 4 or more sites are cocircular, several circle events happen at the
+    // same point. Reuse the vertex from the other end of one of the edges
+    // meeting here, rather than adding a duplicate.
+    let mut vert = None;
+    for &(e, s) in &[(l_edge, a), (r_edge, b)] {
+      let other_end = if self.edges[e].sites[0] == s { 0 } else { 1 };
+      if let Some(v) = self.edges[e].vertices[other_end] {
+        let pv = self.vertices[v];
+        if (pv[0] - center[0]).abs() < self.eps && (pv[1] - center[1]).abs() < self.eps {
+          vert = Some(v);
         }
+      }
+    }
+    let vert = match vert {
+      Some(v) => v,
+      None => { self.vertices.push(center); self.vertices.len() - 1 }
+    };
 
-        /// A function to insert a circle event into the event queue in the right place
-        fn insert_circle(circle: Event, ev_queue: &mut EventQueue) {
-          match circle {
-            Event::Circle(cp, cr, _) => {
-              for ii in 0..ev_queue.len() {
-                match ev_queue[ii] {
-                  Event::Circle(p, r, _) => {
-                    if p[1] + r > cp[1] + cr {
-                      ev_queue.insert(ii, circle);
-                      return;
-                    }
-                  }
-                  Event::Site(p) => {
-                    if p[1] > cp[1] + cr {
-                      ev_queue.insert(ii, circle);
-                      return;
-                    }
-                  }
-                }
-              }
-              ev_queue.push_back(circle);
-            }
-            _ => ()
-          }
-        }
+    self.finish_edge(l_edge, a, vert);
+    self.finish_edge(r_edge, b, vert);
+
+    // The arcs either side now meet, tracing a new edge from this vertex
+    let edge = self.edges.len();
+    self.edges.push(Edge { sites: [a, c], vertices: [Some(vert), None] });
+    self.triangles.push([a, b, c]);
+    self.beach.arcs[prev].r_edge = Some(edge);
+    self.beach.remove(arc);
+
+    self.check_circle_event(prev);
+    self.check_circle_event(next);
+  }
+}
+
+/// Computes the Voronoi diagram of a set of points with Fortune's algorithm,
+/// in O(n log n) time.
+///
+/// Duplicate points are ignored after the first occurrence, and get an empty
+/// cell.
+/// # Params
+/// * `points` - The sites to compute the diagram of
+/// # Returns
+/// The Voronoi diagram, with one cell per point in `points`.
+pub fn voronoi(points: &[[f32; 2]]) -> VoronoiDiagram {
+  let sites : Vec<[f64; 2]> = points.iter().map(|p| [p[0] as f64, p[1] as f64]).collect();
+
+  // Scale the vertex merging distance by the size of the input
+  let extent = sites.iter().fold(1.0f64, |m, p| m.max(p[0].abs()).max(p[1].abs()));
+
+  let mut f = Fortune {
+    sites: &sites,
+    queue: BinaryHeap::with_capacity(sites.len() * 2),
+    event_valid: Vec::new(),
+    beach: BeachLine::new(),
+    vertices: Vec::new(),
+    edges: Vec::new(),
+    triangles: Vec::new(),
+    eps: extent * 1e-9,
+  };
+  for (ii, p) in sites.iter().enumerate() {
+    if p[0].is_finite() && p[1].is_finite() {
+      f.queue.push(Event { y: p[1], x: p[0], kind: EventKind::Site(ii) });
+    }
+  }
 
-        let circle = check_circle_event(&arc_list, lowest_arc_ix, p[1]);
-        if circle.is_some() { 
-          // Find the right index to insert the circle
-          let circle = circle.unwrap();
-          insert_circle(circle, &mut ev_queue);
+  // Process events in order
+  let mut last_site : Option<[f64; 2]> = None;
+  while let Some(e) = f.queue.pop() {
+    match e.kind {
+      EventKind::Site(site) => {
+        // Skip duplicate sites, which are next to each other in the queue
+        if last_site == Some(sites[site]) { continue; }
+        last_site = Some(sites[site]);
+        f.site_event(site);
+      }
+      EventKind::Circle(arc, id, center) => {
+        if f.event_valid[id] { f.circle_event(arc, center); }
+      }
+    }
+  }
+
+  // Drop edges which shrank to a point when merging cocircular vertices
+  let edges : Vec<Edge> = f.edges.into_iter()
+    .filter(|e| e.vertices[0].is_none() || e.vertices[0] != e.vertices[1])
+    .collect();
+
+  // Build the cells from the edges
+  let cells = build_cells(points.len(), &edges);
+  let delaunay = Delaunay::new(points.len(), f.triangles, &edges);
+
+  VoronoiDiagram {
+    sites: points.to_vec(),
+    vertices: f.vertices.iter().map(|v| [v[0] as f32, v[1] as f32]).collect(),
+    edges,
+    cells,
+    bounds: None,
+    weights: None,
+    delaunay,
+  }
+}
+
+/// Builds a list of cells with no polygons, given the edges bordering them.
+fn build_cells(num_sites: usize, edges: &[Edge]) -> Vec<Cell> {
+  let mut cells : Vec<Cell> = (0..num_sites)
+    .map(|ii| Cell { site: ii, edges: Vec::new(), vertices: Vec::new() }).collect();
+  for (ii, e) in edges.iter().enumerate() {
+    cells[e.sites[0]].edges.push(ii);
+    cells[e.sites[1]].edges.push(ii);
+  }
+  cells
+}
+
+/// Computes the Voronoi diagram of a set of points, clipped to a rectangle.
+/// Edges running off to infinity are cut where they leave the rectangle, and
+/// every cell gets a closed, counter-clockwise polygon in `Cell::vertices`.
+///
+/// Sites should lie inside the rectangle - a site outside it may have an empty
+/// cell.
+/// # Params
+/// * `points` - The sites to compute the diagram of
+/// * `bounds` - The rectangle to clip the diagram to - X, Y, W, H, the same
+///   as `CompAABB`
+/// # Returns
+/// The clipped Voronoi diagram, with one cell per point in `points`.
+pub fn voronoi_bounded(points: &[[f32; 2]], bounds: &[f32; 4]) -> VoronoiDiagram {
+  let d = voronoi(points);
+  let rect = Rect::new(bounds);
+
+  // Vertices of the clipped diagram, starting with the vertices of the
+  // unclipped diagram. Vertices outside the rectangle are removed at the end.
+  let mut vertices : Vec<[f64; 2]> = d.vertices.iter().map(|v| [v[0] as f64, v[1] as f64]).collect();
+  let mut edges = Vec::with_capacity(d.edges.len());
+  for e in &d.edges {
+    let (s0, s1) = (points[e.sites[0]], points[e.sites[1]]);
+    // Direction from vertices[0] to vertices[1] - see `Fortune::finish_edge`
+    let dir = [-(s1[1] - s0[1]) as f64, (s1[0] - s0[0]) as f64];
+    let inf = f64::INFINITY;
+    let (origin, dir, t0, t1) = match e.vertices {
+      [Some(v0), Some(v1)] => (vertices[v0], [vertices[v1][0] - vertices[v0][0], vertices[v1][1] - vertices[v0][1]], 0.0, 1.0),
+      [Some(v0), None] => (vertices[v0], dir, 0.0, inf),
+      [None, Some(v1)] => (vertices[v1], dir, -inf, 0.0),
+      [None, None] => ([(s0[0] + s1[0]) as f64 / 2.0, (s0[1] + s1[1]) as f64 / 2.0], dir, -inf, inf),
+    };
+
+    let (c0, c1) = match rect.clip(origin, dir, t0, t1) { Some(t) => t, None => continue };
+
+    // Keep the original vertices if they're inside the rectangle, otherwise
+    // add new vertices where the edge leaves it
+    let mut ends = [None; 2];
+    for &(end, t, c) in &[(0, t0, c0), (1, t1, c1)] {
+      ends[end] = if t == c { e.vertices[end] } else {
+        vertices.push([origin[0] + dir[0]*c, origin[1] + dir[1]*c]);
+        Some(vertices.len() - 1)
+      };
+    }
+    edges.push(Edge { sites: e.sites, vertices: ends });
+  }
+
+  // Build the polygon of each cell. Orient each edge so the cell's site is on
+  // the left, then sort the edges counter-clockwise around the site. Any gaps
+  // between consecutive edges are on the boundary of the rectangle, and are
+  // filled by walking along it, adding the corners passed.
+  let mut cells = build_cells(points.len(), &edges);
+  let mut corners = [None; 4];
+  for cell in &mut cells {
+    let site = [points[cell.site][0] as f64, points[cell.site][1] as f64];
+    let mut sides : Vec<(f64, usize, usize)> = cell.edges.iter().map(|&e| {
+      let e = &edges[e];
+      let (a, b) = if e.sites[0] == cell.site { (e.vertices[0], e.vertices[1]) }
+                   else { (e.vertices[1], e.vertices[0]) };
+      let (a, b) = (a.unwrap(), b.unwrap());
+      let mid = [(vertices[a][0] + vertices[b][0]) / 2.0, (vertices[a][1] + vertices[b][1]) / 2.0];
+      ((mid[1] - site[1]).atan2(mid[0] - site[0]), a, b)
+    }).collect();
+    sides.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    if sides.is_empty() {
+      // No edges cross the rectangle, so this cell either contains all of it
+      // or none of it. Only the site nearest the center contains it, and if
+      // that site is duplicated, only the first copy.
+      let center = [rect.min[0] + rect.w / 2.0, rect.min[1] + rect.h / 2.0];
+      let dist = |p: &[f32; 2]| (p[0] as f64 - center[0]).powi(2) + (p[1] as f64 - center[1]).powi(2);
+      let nearest = (0..points.len()).fold(0, |n, ii| if dist(&points[ii]) < dist(&points[n]) { ii } else { n });
+      if nearest == cell.site {
+        for ii in 0..4 { cell.vertices.push(rect.corner(ii, &mut corners, &mut vertices)); }
+      }
+      continue;
+    }
+
+    for ii in 0..sides.len() {
+      let (_, a, b) = sides[ii];
+      let next_a = sides[(ii + 1) % sides.len()].1;
+      cell.vertices.push(a);
+      if b != next_a {
+        cell.vertices.push(b);
+        for c in rect.corners_between(vertices[b], vertices[next_a]) {
+          cell.vertices.push(rect.corner(c, &mut corners, &mut vertices));
         }
-        let circle = check_circle_event(&arc_list, lowest_arc_ix+2, p[1]);
-        if circle.is_some() { 
-          // Find the right index to insert the circle
-          let circle = circle.unwrap();
-          insert_circle(circle, &mut ev_queue);
+      }
+    }
+  }
+
+  // Remove vertices which aren't used by any edge or cell
+  let mut remap = vec![None; vertices.len()];
+  let mut used_vertices = Vec::new();
+  {
+    let mut use_vertex = |v: usize| -> usize {
+      *remap[v].get_or_insert_with(|| {
+        used_vertices.push([vertices[v][0] as f32, vertices[v][1] as f32]);
+        used_vertices.len() - 1
+      })
+    };
+    for e in &mut edges {
+      for v in e.vertices.iter_mut() { *v = v.map(&mut use_vertex); }
+    }
+    for c in &mut cells {
+      for v in c.vertices.iter_mut() { *v = use_vertex(*v); }
+    }
+  }
+
+  VoronoiDiagram {
+    sites: points.to_vec(),
+    vertices: used_vertices,
+    edges,
+    cells,
+    bounds: Some(*bounds),
+    weights: None,
+    delaunay: d.delaunay,
+  }
+}
+
+/// Computes the power diagram (weighted Voronoi diagram) of a set of points,
+/// clipped to a rectangle. A point is in the cell of the site minimising
+/// `distance^2 - weight`, so sites with larger weights get larger cells. With
+/// equal weights this is the same as `voronoi_bounded()`.
+///
+/// A site with a small weight can be swallowed completely by its neighbours,
+/// leaving it with an empty cell, and a site isn't always inside its own cell.
+/// `VoronoiDiagram::delaunay` is the weighted (regular) triangulation. If the
+/// weights differ, it only links sites whose cells share an edge inside the
+/// rectangle. If they're all equal, the diagram is built by
+/// `voronoi_bounded()`, so everything but `weights` matches it exactly.
+/// # Params
+/// * `points` - The sites to compute the diagram of
+/// * `weights` - The weight of each site, in squared distance units. A weight
+///   of `r*r` roughly pushes the cell's border `r/2` further out.
+/// * `bounds` - The rectangle to clip the diagram to - X, Y, W, H
+/// # Returns
+/// The clipped power diagram, with one cell per point in `points`.
+pub fn voronoi_weighted(points: &[[f32; 2]], weights: &[f32], bounds: &[f32; 4]) -> VoronoiDiagram {
+  assert_eq!(points.len(), weights.len(), "Every site needs a weight");
+  let index = SiteIndex::new(points);
+  let (min, max) = weights.iter().fold((f64::MAX, f64::MIN), |(lo, hi), &w| (lo.min(w as f64), hi.max(w as f64)));
+  let spread = (max - min).max(0.0);
+  if spread == 0.0 {
+    let mut d = voronoi_bounded(points, bounds);
+    d.weights = Some(weights.to_vec());
+    return d;
+  }
+
+  let polygons : Vec<CellPolygon> = (0..points.len()).map(|ii| {
+    let a = [points[ii][0] as f64, points[ii][1] as f64];
+    let mut cell = polygon::rect_polygon(bounds);
+    // Clip by the other sites, nearest first. A site `d` away can only cut
+    // the cell if its border is closer than the furthest corner, and the
+    // border is at least `(d^2 - spread) / 2d` away, so stop once that's past
+    // the furthest corner.
+    let mut k = 16;
+    let mut done = 0;
+    'search: loop {
+      let nearest = index.k_nearest(points[ii], k);
+      for &jj in &nearest[done.min(nearest.len())..] {
+        done += 1;
+        if jj == ii { continue; }
+        let b = [points[jj][0] as f64, points[jj][1] as f64];
+        let offset = (weights[jj] - weights[ii]) as f64;
+        let d2 = (b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2);
+        if d2 == 0.0 && offset == 0.0 {
+          // The same site twice - the first one gets the cell
+          if jj < ii { cell.clear(); break 'search; }
+          continue;
         }
+        let reach = cell.iter().fold(0.0f64, |m, c| m.max((c.pos[0] - a[0]).powi(2) + (c.pos[1] - a[1]).powi(2)));
+        if d2 > 0.0 && d2 - spread > 0.0 && (d2 - spread).powi(2) / (4.0 * d2) >= reach * (1.0 + 1e-6) { break 'search; }
+        cell = polygon::clip_bisector(&cell, a, b, offset, jj);
+        if cell.is_empty() { break 'search; }
+      }
+      if nearest.len() < k { break; }
+      k *= 4;
+    }
+    cell
+  }).collect();
+
+  let mut d = polygon::assemble(points, bounds, &polygons);
+  d.weights = Some(weights.to_vec());
+  d
+}
+
+/// A clipping rectangle, in doubles.
+struct Rect {
+  min: [f64; 2],
+  w: f64,
+  h: f64,
+}
+
+impl Rect {
+  fn new(aabb: &[f32; 4]) -> Rect {
+    Rect { min: [aabb[0] as f64, aabb[1] as f64], w: aabb[2] as f64, h: aabb[3] as f64 }
+  }
+
+  /// Clips the line `origin + t*dir` for `t` in `[t0, t1]` to this rectangle
+  /// with the Liang-Barsky algorithm.
+  /// # Returns
+  /// The clipped range of `t`, or `None` if the line is outside the rectangle.
+  fn clip(&self, origin: [f64; 2], dir: [f64; 2], mut t0: f64, mut t1: f64) -> Option<(f64, f64)> {
+    let max = [self.min[0] + self.w, self.min[1] + self.h];
+    let checks = [(-dir[0], origin[0] - self.min[0]), (dir[0], max[0] - origin[0]),
+                  (-dir[1], origin[1] - self.min[1]), (dir[1], max[1] - origin[1])];
+    for &(p, q) in &checks {
+      if p == 0.0 {
+        if q < 0.0 { return None; }
+        continue;
       }
+      let r = q / p;
+      if p < 0.0 { t0 = t0.max(r); } else { t1 = t1.min(r); }
+    }
+    // Also reject edges which only touch the rectangle at a point
+    let len = (dir[0]*dir[0] + dir[1]*dir[1]).sqrt();
+    if (t1 - t0) * len <= (self.w + self.h) * 1e-9 { None } else { Some((t0, t1)) }
+  }
+
+  /// The distance of a point on the boundary from the first corner, going
+  /// counter-clockwise.
+  fn perimeter_pos(&self, p: [f64; 2]) -> f64 {
+    let (dx, dy) = (p[0] - self.min[0], p[1] - self.min[1]);
+    // Find the closest side, in counter-clockwise order from the first corner
+    let dists = [dy.abs(), (dx - self.w).abs(), (dy - self.h).abs(), dx.abs()];
+    let side = (0..4).fold(0, |s, ii| if dists[ii] < dists[s] { ii } else { s });
+    match side {
+      0 => dx,
+      1 => self.w + dy,
+      2 => self.w + self.h + (self.w - dx),
+      _ => 2.0*self.w + self.h + (self.h - dy),
+    }
+  }
+
+  /// The indices of the corners passed when walking counter-clockwise along
+  /// the boundary from `from` to `to`.
+  fn corners_between(&self, from: [f64; 2], to: [f64; 2]) -> Vec<usize> {
+    let perimeter = 2.0 * (self.w + self.h);
+    let corner_pos = [0.0, self.w, self.w + self.h, 2.0*self.w + self.h];
+    let start = self.perimeter_pos(from);
+    let mut dist = self.perimeter_pos(to) - start;
+    if dist < 0.0 { dist += perimeter; }
 
-      // Process circle event
-      Event::Circle(p, rad, id) => { 
-        let mut neighbours = [None; 2];
-
-        // Draw circles
-        r.rect(&[p[0], p[1]+rad, 2.0, 2.0], &[0.0, 1.0, 0.0, 1.0]); 
-
-        let new_node = Node { neighbour_list: Vec::new(), pos: p.clone() };
-        node_list.push(new_node);
-        let new_node_ix = node_list.len() - 1;
-
-        // Find the arc that just had its size reduced to 0
-        for ii in 0..arc_list.len() {
-          if arc_list[ii].id == id {
-            // Get neighbour vertices for new node
-            if arc_list[ii].l_vert.is_some() { neighbours[0] = Some(arc_list[ii].l_vert.unwrap()); }
-            if arc_list[ii].r_vert.is_some() { neighbours[1] = Some(arc_list[ii].r_vert.unwrap()); }
-            // Add connections to the old vertices
-            if neighbours[0].is_some() { node_list[neighbours[0].unwrap()].neighbour_list.push(new_node_ix); }
-            if neighbours[1].is_some() { node_list[neighbours[1].unwrap()].neighbour_list.push(new_node_ix); }
-            // Set the arcs to the left & the right's new v_right and v_left
-            if ii > 0 { arc_list[ii-1].r_vert = Some(new_node_ix); }
-            if ii < arc_list.len()-1 { arc_list[ii+1].l_vert = Some(new_node_ix); }
-            // Remove the arc
-            arc_list.remove(ii);
-            break;
-          }
+    let mut corners : Vec<(f64, usize)> = (0..4).filter_map(|ii| {
+      let mut d = corner_pos[ii] - start;
+      if d < 0.0 { d += perimeter; }
+      if d > 0.0 && d < dist { Some((d, ii)) } else { None }
+    }).collect();
+    corners.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    corners.into_iter().map(|c| c.1).collect()
+  }
+
+  /// Gets the vertex index of a corner, adding it to the vertex list the first
+  /// time it's used. Corners are numbered counter-clockwise from the min corner.
+  fn corner(&self, ii: usize, corners: &mut [Option<usize>; 4], vertices: &mut Vec<[f64; 2]>) -> usize {
+    *corners[ii].get_or_insert_with(|| {
+      let (x, y) = (self.min[0], self.min[1]);
+      vertices.push(match ii {
+        0 => [x, y],
+        1 => [x + self.w, y],
+        2 => [x + self.w, y + self.h],
+        _ => [x, y + self.h],
+      });
+      vertices.len() - 1
+    })
+  }
+}
+
+/// Draws a Voronoi diagram for debugging. Sites are drawn as red squares,
+/// vertices as green squares, and finite edges as cyan lines.
+/// # Params
+/// * `d` - The diagram to draw
+/// * `r` - The renderer controller to send the geometry to
+pub fn draw_voronoi(d: &VoronoiDiagram, r: &RendererController) {
+  // Draw sites
+  for p in &d.sites { r.rect(&[p[0], p[1], 2.0, 2.0], &[1.0, 0.0, 0.0, 1.0]); }
+
+  // Draw vertices
+  for p in &d.vertices { r.rect(&[p[0], p[1], 2.0, 2.0], &[0.0, 1.0, 0.0, 1.0]); }
+
+  // Draw edges
+  for e in &d.edges {
+    if let [Some(v0), Some(v1)] = e.vertices {
+      let (p0, p1) = (d.vertices[v0], d.vertices[v1]);
+      r.line(Vector2::new(p0[0], p0[1]), Vector2::new(p1[0], p1[1]), 1.0, [0.0, 1.0, 1.0, 1.0]);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::{Rng, SeedableRng, XorShiftRng};
+
+  const BOUNDS : [f32; 4] = [0.0, 0.0, 800.0, 600.0];
+
+  /// Checks the diagram of some points has a cell per site, that neighbours
+  /// agree with each other, and that each vertex is the center of a circle
+  /// through the sites around it with no other site inside.
+  fn check_diagram(points: &[[f32; 2]]) -> VoronoiDiagram {
+    let d = voronoi(points);
+    assert_eq!(d.cells.len(), points.len());
+    let dist = |v: [f32; 2], s: usize| {
+      let (dx, dy) = ((v[0] - points[s][0]) as f64, (v[1] - points[s][1]) as f64);
+      (dx*dx + dy*dy).sqrt()
+    };
+    for (ii, e) in d.edges.iter().enumerate() {
+      assert!(d.cells[e.sites[0]].edges.contains(&ii) && d.cells[e.sites[1]].edges.contains(&ii),
+              "edge {} is missing from the cells it borders", ii);
+      for v in e.vertices.iter().filter_map(|v| *v) {
+        let v = d.vertices[v];
+        let r = dist(v, e.sites[0]);
+        assert!((r - dist(v, e.sites[1])).abs() <= 1e-2 * r.max(1.0), "vertex {:?} is off edge {}", v, ii);
+        for s in 0..points.len() {
+          assert!(dist(v, s) >= r - 1e-2 * r.max(1.0), "site {} is inside the circle at {:?}", s, v);
         }
+      }
+    }
+    for (a, n) in d.delaunay.neighbours.iter().enumerate() {
+      assert_eq!(*n, neighbours(&d, a));
+    }
+    d
+  }
+
+  /// Checks the diagram of some points clipped to `BOUNDS` has a cell per
+  /// site, that the cells cover the bounds, and that each edge is in the
+  /// cells it borders.
+  fn check_bounded(points: &[[f32; 2]]) -> VoronoiDiagram {
+    let d = voronoi_bounded(points, &BOUNDS);
+    assert_eq!(d.cells.len(), points.len());
+    let total : f32 = (0..points.len()).map(|ii| d.cell_area(ii)).sum();
+    let expected = BOUNDS[2] * BOUNDS[3];
+    assert!((total - expected).abs() <= expected * 1e-4, "cell areas sum to {}, not {}", total, expected);
+    for (ii, e) in d.edges.iter().enumerate() {
+      assert!(d.cells[e.sites[0]].edges.contains(&ii) && d.cells[e.sites[1]].edges.contains(&ii),
+              "edge {} is missing from the cells it borders", ii);
+    }
+    d
+  }
+
+  /// Gets the sorted neighbours of a site, from the edges of its cell.
+  fn neighbours(d: &VoronoiDiagram, site: usize) -> Vec<usize> {
+    let mut n : Vec<usize> = d.cells[site].edges.iter()
+      .map(|&e| d.edges[e].sites).map(|s| if s[0] == site { s[1] } else { s[0] }).collect();
+    n.sort();
+    n.dedup();
+    n
+  }
 
-        // Add neighbours to the new node's neighbour list
-        if neighbours[0].is_some() { node_list[new_node_ix].neighbour_list.push(neighbours[0].unwrap()); }
-        if neighbours[1].is_some() { node_list[new_node_ix].neighbour_list.push(neighbours[1].unwrap()); }
+  #[test]
+  fn grid() {
+    // The test data from main.rs, with each column shifted down slightly
+    let mut points = vec![];
+    for ii in 0..4 {
+      for jj in 0..4 {
+        points.push([100.0 + (ii as f32) * 50.0, 100.0 + (jj as f32) * 50.0 + ii as f32]);
       }
     }
+    let d = check_diagram(&points);
+    // Each site neighbours the sites above, below, left and right of it
+    for ii in 0..4 {
+      for jj in 0..4 {
+        let site = ii * 4 + jj;
+        if jj < 3 { assert!(neighbours(&d, site).contains(&(site + 1))); }
+        if ii < 3 { assert!(neighbours(&d, site).contains(&(site + 4))); }
+      }
+    }
+    let d = check_bounded(&points);
+    for ii in 0..points.len() { assert!(d.cell_area(ii) > 0.0, "site {} has an empty cell", ii); }
+  }
+
+  #[test]
+  fn collinear_sites() {
+    let points : Vec<[f32; 2]> = (0..10).map(|ii| [50.0 + ii as f32 * 70.0, 50.0 + ii as f32 * 50.0]).collect();
+    let d = check_diagram(&points);
+    assert!(d.vertices.is_empty());
+    check_bounded(&points);
+    for ii in 0..points.len() {
+      let expected : Vec<usize> = (0..points.len()).filter(|&jj| jj + 1 == ii || jj == ii + 1).collect();
+      assert_eq!(neighbours(&d, ii), expected);
+    }
+
+    // Vertical lines are processed one site at a time, with no sites sharing
+    // a y coordinate
+    let points : Vec<[f32; 2]> = (0..10).map(|ii| [400.0, 20.0 + ii as f32 * 60.0]).collect();
+    let d = check_diagram(&points);
+    assert_eq!(neighbours(&d, 5), vec![4, 6]);
+    check_bounded(&points);
   }
 
-  // Draw graph
-  // We don't really care about 'overdraw', so we can double up edge drawing. It's only debug
-  for n in &node_list {
-    for n2 in &n.neighbour_list {
-      r.line(Vector2::new(n.pos[0], n.pos[1]), 
-             Vector2::new(node_list[*n2].pos[0], node_list[*n2].pos[1]), 
-             1.0, 
-             [0.0, 1.0, 1.0, 1.0]);
+  #[test]
+  fn sites_sharing_y() {
+    // A single row, which never has an arc above to split
+    let points : Vec<[f32; 2]> = (0..10).map(|ii| [30.0 + ii as f32 * 75.0, 300.0]).collect();
+    let d = check_diagram(&points);
+    assert_eq!(neighbours(&d, 3), vec![2, 4]);
+    check_bounded(&points);
+
+    // Rows of sites, starting with a row, given out of order
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let mut points = vec![];
+    for jj in 0..6 {
+      for ii in 0..8 {
+        points.push([50.0 + ii as f32 * 100.0 + rng.gen_range(-20.0, 20.0), 50.0 + jj as f32 * 100.0]);
+      }
+    }
+    rng.shuffle(&mut points);
+    let d = check_diagram(&points);
+    for ii in 0..points.len() { assert!(!d.cells[ii].edges.is_empty(), "site {} has no cell", ii); }
+    let d = check_bounded(&points);
+    for ii in 0..points.len() { assert!(d.cell_area(ii) > 0.0, "site {} has an empty cell", ii); }
+  }
+
+  #[test]
+  fn equal_weights_match_bounded() {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let points : Vec<[f32; 2]> = (0..100)
+      .map(|_| [rng.gen_range(0.0, BOUNDS[2]), rng.gen_range(0.0, BOUNDS[3])]).collect();
+    let bounded = voronoi_bounded(&points, &BOUNDS);
+    for &w in &[0.0, 500.0] {
+      let weighted = voronoi_weighted(&points, &vec![w; points.len()], &BOUNDS);
+      assert_eq!(weighted.weights, Some(vec![w; points.len()]));
+      assert_eq!(VoronoiDiagram { weights: None, .. weighted }, bounded);
+    }
+  }
+
+  #[test]
+  fn duplicate_sites() {
+    // Repeat some random sites, both before and after the original
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let mut points : Vec<[f32; 2]> = (0..40)
+      .map(|_| [rng.gen_range(0.0, BOUNDS[2]), rng.gen_range(0.0, BOUNDS[3])]).collect();
+    for ii in 0..20 {
+      let p = points[ii * 2];
+      points.push(p);
+      points.insert(0, p);
+    }
+    let d = check_diagram(&points);
+    let bounded = check_bounded(&points);
+    // Tiny weights, which barely move the borders, but which differ so the
+    // cells are built by clipping rather than by `voronoi_bounded()`
+    let weights : Vec<f32> = points.iter().map(|p| p[0] * 1e-4).collect();
+    let weighted = voronoi_weighted(&points, &weights, &BOUNDS);
+    for ii in 0..points.len() {
+      let first = points.iter().position(|p| *p == points[ii]).unwrap();
+      if first == ii {
+        assert!(!d.cells[ii].edges.is_empty(), "site {} has no cell", ii);
+        assert!(bounded.cell_area(ii) > 0.0, "site {} has an empty cell", ii);
+      }
+      else {
+        assert!(d.cells[ii].edges.is_empty(), "site {} is a duplicate of site {}, but has a cell", ii, first);
+        assert_eq!(bounded.cell_area(ii), 0.0, "site {} is a duplicate of site {}, but has a cell", ii, first);
+      }
+      assert!((bounded.cell_area(ii) - weighted.cell_area(ii)).abs() <= 1.0,
+              "area of site {}: {} vs {} weighted", ii, bounded.cell_area(ii), weighted.cell_area(ii));
     }
   }
 }